@@ -0,0 +1,213 @@
+//! Results of `cached` rule generators kept across runs in `.cache` of the workplace.
+//!
+//! Every generator has a file named by a hash of its definition and of every
+//! enabled generator of the groups it depends on, with their positions in the
+//! config which cached results refer to, so editing or moving any of them
+//! starts the generator over with an empty cache. Every set of enabled
+//! extensions has a folder of its own, so switching between them keeps the
+//! caches of the others.
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::config::{Config, RuntimeOptions};
+use crate::lang::{LangItem, LangResult};
+use crate::meta::GeneratorMeta;
+use crate::result::SubProvenance;
+use crate::rule::Rule;
+use crate::output::write_atomic;
+
+/// Bumped whenever the content of cache files changes.
+const CACHE_VERSION: &str = "1";
+
+/// `.cache` in the workplace.
+pub fn cache_dir(workplace: &Path) -> PathBuf {
+    workplace.join(".cache")
+}
+
+/// `.cache/generators/<extensions>` in the workplace, `default` without any extension enabled.
+pub fn generator_cache_dir(options: &RuntimeOptions) -> PathBuf {
+    let mut extensions: Vec<String> = options.enabled_extensions().into_iter().collect();
+    extensions.sort();
+    let name = if extensions.is_empty() { "default".to_string() } else { extensions.join(",") };
+    cache_dir(Path::new(&options.workplace_path)).join("generators").join(name)
+}
+
+/// A generated result, with the narrowed namespace, extensions and priority of its meta
+/// and the results its placeholders matched.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CachedResult {
+    pub namespace: String,
+    pub extensions: Vec<String>,
+    pub priority: i32,
+    /// Position of the rule in the generator
+    pub rule: Option<usize>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subs: Vec<SubProvenance>,
+    pub target: String,
+}
+
+impl CachedResult {
+    pub fn new(meta: &GeneratorMeta, rule: Option<usize>, subs: Vec<SubProvenance>, target: &str) -> Self {
+        let mut extensions: Vec<String> = meta.extensions.iter().cloned().collect();
+        extensions.sort();
+        CachedResult {
+            namespace: meta.namespace.clone(),
+            extensions,
+            priority: meta.priority,
+            rule,
+            subs,
+            target: target.to_string(),
+        }
+    }
+
+    /// The result of the generator at `generator` in `config` for `source` that this was made of.
+    pub fn to_result<'a>(&self, config: &'a Config, generator: usize, source: &str) -> Box<dyn LangResult<'a> + 'a> {
+        let definition = &config.generators[generator];
+        let mut meta = definition.header();
+        meta.namespace = self.namespace.clone();
+        meta.extensions = self.extensions.iter().cloned().collect();
+        meta.priority = self.priority;
+        Box::new(CachedLangResult {
+            generator,
+            meta,
+            rule: self.rule.and_then(|x| definition.rules.as_ref()?.get(x)),
+            subs: self.subs.iter().map(|x| sub_result(config, x)).collect(),
+            result: vec![(source.to_string(), self.target.clone())],
+        })
+    }
+}
+
+/// The result matched by a placeholder as kept in the cache, only with its group and namespace as meta.
+fn sub_result<'a>(config: &'a Config, sub: &SubProvenance) -> Box<dyn LangResult<'a> + 'a> {
+    Box::new(CachedLangResult {
+        generator: sub.generator,
+        meta: GeneratorMeta { group: sub.group.clone(), namespace: sub.namespace.clone(), ..GeneratorMeta::default() },
+        rule: sub.rule.and_then(|x| config.generators.get(sub.generator)?.rules.as_ref()?.get(x)),
+        subs: sub.subs.iter().map(|x| sub_result(config, x)).collect(),
+        result: vec![(sub.source.clone(), sub.target.clone())],
+    })
+}
+
+/// A result read from the cache.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedLangResult<'a> {
+    pub generator: usize,
+    pub meta: GeneratorMeta,
+    pub rule: Option<&'a Rule>,
+    pub subs: Vec<Box<dyn LangResult<'a>>>,
+    pub result: Vec<LangItem>,
+}
+
+impl<'a> LangResult<'a> for CachedLangResult<'a> {
+    fn meta(&self) -> Cow<'a, GeneratorMeta> {
+        Cow::Owned(self.meta.clone())
+    }
+
+    fn result(&self) -> Cow<'_, Vec<LangItem>> {
+        Cow::Borrowed(&self.result)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.result.is_empty()
+    }
+
+    fn generator(&self) -> usize {
+        self.generator
+    }
+
+    fn rule(&self) -> Option<&'a Rule> {
+        self.rule
+    }
+
+    fn subs(&self) -> &[Box<dyn LangResult<'a>>] {
+        &self.subs
+    }
+}
+
+/// The definition of `meta` as canonical JSON, dicts and extensions sorted.
+fn definition(meta: &GeneratorMeta) -> String {
+    let mut value = serde_json::to_value(meta).expect("config is valid JSON");
+    if let Some(extensions) = value.get_mut("extensions").and_then(|x| x.as_array_mut()) {
+        extensions.sort_by(|a, b| a.as_str().cmp(&b.as_str()));
+    }
+    value.to_string()
+}
+
+/// Hex digest of the definitions of `metas` and their positions in `Config::generators`, in order.
+pub fn digest<'m, I>(metas: I) -> String where I: IntoIterator<Item = (usize, &'m GeneratorMeta)> {
+    let mut hasher = Sha256::new();
+    hasher.update(CACHE_VERSION);
+    for (position, meta) in metas {
+        hasher.update(position.to_string());
+        hasher.update([0]);
+        hasher.update(definition(meta));
+        hasher.update([0]);
+    }
+    hasher.finalize().iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+/// Hex digest of `generator` and the generators it depends on, in order, with their positions.
+pub fn cache_key<'m, I>(generator: (usize, &'m GeneratorMeta), dependencies: I) -> String
+    where I: IntoIterator<Item = (usize, &'m GeneratorMeta)> {
+    digest(std::iter::once(generator).chain(dependencies))
+}
+
+/// Results of one generator keyed by source text, a source without results included.
+#[derive(Debug, Clone)]
+pub struct GeneratorCache {
+    pub key: String,
+    results: BTreeMap<String, Vec<CachedResult>>,
+    changed: bool,
+}
+
+impl GeneratorCache {
+    /// The cache of `key` in `dir`, empty if there is none or it cannot be read.
+    pub fn load(dir: &Path, key: String) -> Self {
+        let results = fs::read(dir.join(format!("{}.json", key))).ok()
+            .and_then(|x| serde_json::from_slice(&x).ok())
+            .unwrap_or_default();
+        GeneratorCache { key, results, changed: false }
+    }
+
+    pub fn get(&self, source: &str) -> Option<&[CachedResult]> {
+        self.results.get(source).map(|x| x.as_slice())
+    }
+
+    pub fn insert(&mut self, source: String, results: Vec<CachedResult>) {
+        self.results.insert(source, results);
+        self.changed = true;
+    }
+
+    /// Writes the cache to `dir` if anything was added since loading.
+    pub fn save(&self, dir: &Path) -> std::io::Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        fs::create_dir_all(dir)?;
+        let contents = serde_json::to_vec(&self.results).expect("strings are valid JSON");
+        write_atomic(&dir.join(format!("{}.json", self.key)), &contents)
+    }
+}
+
+/// Removes the cache files in `dir` other than those of `keys`, the caches of
+/// other sets of extensions are in other folders.
+pub fn prune(dir: &Path, keys: &HashSet<&str>) -> std::io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        let stale = path.extension().is_some_and(|x| x == "json")
+            && path.file_stem().and_then(|x| x.to_str()).is_some_and(|x| !keys.contains(x));
+        if stale {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
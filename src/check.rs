@@ -0,0 +1,392 @@
+//! Static checks of the config, reported by `gt6tg check`.
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use either::Either;
+use serde::de::{IgnoredAny, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use crate::config::Config;
+use crate::forge;
+use crate::graph::{cycle_path, GroupGraph};
+use crate::meta::GeneratorMeta;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Like `config.yml: generators[3]`
+    pub location: String,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}: {}", severity, self.location, self.message)
+    }
+}
+
+/// Runs every check on `config`, errors first.
+pub fn check(config: &Config) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    check_rules(config, &mut diagnostics);
+    check_groups(config, &mut diagnostics);
+    check_cycles(config, &mut diagnostics);
+    check_dict_keys(config, &mut diagnostics);
+    check_duplicate_entries(config, &mut diagnostics);
+    check_namespaces(config, &mut diagnostics);
+    diagnostics.sort_by_key(|x| x.severity);
+    diagnostics
+}
+
+/// Status `gt6tg check` exits with, 1 if there is an error.
+pub fn exit_status(diagnostics: &[Diagnostic]) -> i32 {
+    if diagnostics.iter().any(|x| x.severity == Severity::Error) { 1 } else { 0 }
+}
+
+fn diagnostic(severity: Severity, location: String, message: String) -> Diagnostic {
+    Diagnostic { severity, location, message }
+}
+
+/// Groups referred to by subs, with the placeholders of each rule.
+fn check_rules(config: &Config, diagnostics: &mut Vec<Diagnostic>) {
+    let defined: HashSet<&str> = config.generators.iter().map(|x| x.group.as_str()).collect();
+    for (i, meta) in config.generators.iter().enumerate() {
+        for (j, rule) in meta.rules.iter().flatten().enumerate() {
+            let location = format!("{}.rules[{}]", config.generator_location(i), j);
+            let error = |message: String| diagnostic(Severity::Error, location.clone(), message);
+            let subs = rule.subs.len();
+            if subs == 0 {
+                diagnostics.push(error(format!("rule '{}' has no subs and never matches", rule.source)));
+                continue;
+            }
+            for sub in rule.subs.iter().filter(|x| !defined.contains(x.as_str())) {
+                diagnostics.push(error(format!("rule '{}' refers to undefined group '{}'", rule.source, sub)));
+            }
+            let source: BTreeSet<usize> = rule.source_template().placeholders().collect();
+            let target: BTreeSet<usize> = rule.target_template().placeholders().collect();
+            let missing: Vec<String> = (0..subs).filter(|x| !source.contains(x)).map(|x| format!("{{{}}}", x)).collect();
+            if !missing.is_empty() {
+                diagnostics.push(error(format!("source '{}' has {} subs but lacks {}", rule.source, subs, missing.join(", "))));
+            }
+            for (name, template, placeholders) in [("source", &rule.source, &source), ("target", &rule.target, &target)] {
+                let extra: Vec<String> = placeholders.iter().filter(|x| **x >= subs).map(|x| format!("{{{}}}", x)).collect();
+                if !extra.is_empty() {
+                    diagnostics.push(error(format!("{} '{}' has {} subs but uses {}", name, template, subs, extra.join(", "))));
+                }
+            }
+        }
+    }
+}
+
+/// Groups neither referred to by a sub nor completed.
+fn check_groups(config: &Config, diagnostics: &mut Vec<Diagnostic>) {
+    let used: HashSet<&str> = config.generators.iter()
+        .flat_map(|x| x.rules.iter().flatten())
+        .flat_map(|x| x.subs.iter().map(|x| x.as_str()))
+        .collect();
+    // first generator of each group, and whether any is completed
+    let mut groups: BTreeMap<&str, (usize, bool)> = BTreeMap::new();
+    for (i, meta) in config.generators.iter().enumerate() {
+        groups.entry(meta.group.as_str()).or_insert((i, false)).1 |= meta.completed;
+    }
+    for (group, (first, completed)) in groups {
+        if !completed && !used.contains(group) {
+            diagnostics.push(diagnostic(Severity::Warning, config.generator_location(first),
+                format!("group '{}' is not completed and not used by any rule, it never takes effect", group)));
+        }
+    }
+}
+
+/// Groups referring to themselves through the subs of their rules.
+fn check_cycles(config: &Config, diagnostics: &mut Vec<Diagnostic>) {
+    if let Err(cycles) = GroupGraph::new(config.generators.iter()).check_acyclic() {
+        for cycle in cycles.0 {
+            let first = config.generators.iter().position(|x| x.group == cycle[0]).unwrap_or_default();
+            diagnostics.push(diagnostic(Severity::Error, config.generator_location(first),
+                format!("group '{}' depends on itself: {}", cycle[0], cycle_path(&cycle))));
+        }
+    }
+}
+
+/// Whether generators requiring `a` and `b` can be enabled at the same time.
+fn compatible(a: &GeneratorMeta, b: &GeneratorMeta) -> bool {
+    let excludes = |x: &GeneratorMeta, y: &GeneratorMeta| x.extensions.iter()
+        .filter_map(|e| e.strip_prefix('!'))
+        .any(|e| y.extensions.contains(e));
+    !excludes(a, b) && !excludes(b, a)
+}
+
+/// Group, namespace name and key of a dict entry.
+type DictKey<'c> = (&'c str, String, &'c str);
+
+/// Dict keys defined by several generators of a group for the same namespace, compared by name.
+fn check_dict_keys(config: &Config, diagnostics: &mut Vec<Diagnostic>) {
+    let mut seen: HashMap<DictKey, Vec<(usize, &str)>> = HashMap::new();
+    for (i, meta) in config.generators.iter().enumerate() {
+        for (key, value) in meta.dict.iter().flatten() {
+            match &value.inner {
+                Either::Left(target) => {
+                    seen.entry((&meta.group, forge::namespace_name(&meta.namespace), key)).or_default().push((i, target));
+                }
+                Either::Right(map) => {
+                    for (namespace, target) in map.iter() {
+                        let mut ns_meta = meta.header();
+                        ns_meta.combine(&meta.header().with_namespace(namespace.to_string()));
+                        if !ns_meta.is_empty() {
+                            seen.entry((&meta.group, forge::namespace_name(&ns_meta.namespace), key)).or_default().push((i, target));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    let mut duplicates: Vec<_> = seen.into_iter().filter(|(_, x)| x.len() > 1).collect();
+    duplicates.sort();
+    for ((group, namespace, key), definitions) in duplicates {
+        let (first, first_target) = definitions[0];
+        for (i, target) in definitions.into_iter().skip(1) {
+            if !compatible(&config.generators[first], &config.generators[i]) {
+                continue;
+            }
+            let scope = if namespace.is_empty() { String::new() } else { format!(" in namespace '{}'", namespace) };
+            let (severity, message) = if target == first_target {
+                (Severity::Warning, format!("key '{}' of group '{}'{} is also defined at {}", key, group, scope, config.generator_location(first)))
+            } else {
+                (Severity::Error, format!("key '{}' of group '{}'{} is '{}' here but '{}' at {}", key, group, scope, target, first_target, config.generator_location(first)))
+            };
+            diagnostics.push(diagnostic(severity, config.generator_location(i), message));
+        }
+    }
+}
+
+/// Keys of a dict mapping in the order written, duplicates included.
+struct DictKeys(Vec<String>);
+
+impl<'de> Deserialize<'de> for DictKeys {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeysVisitor;
+
+        impl<'de> Visitor<'de> for KeysVisitor {
+            type Value = DictKeys;
+
+            fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+                formatter.write_str("a dict")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut keys = vec![];
+                while let Some((key, IgnoredAny)) = map.next_entry::<String, IgnoredAny>()? {
+                    keys.push(key);
+                }
+                Ok(DictKeys(keys))
+            }
+        }
+
+        deserializer.deserialize_map(KeysVisitor)
+    }
+}
+
+#[derive(Deserialize)]
+struct RawGenerator {
+    #[serde(default)]
+    group: String,
+    dict: Option<DictKeys>,
+}
+
+#[derive(Deserialize)]
+struct RawConfig {
+    #[serde(default)]
+    generators: Vec<RawGenerator>,
+}
+
+/// Keys written twice in one dict, of which only the last is read.
+fn check_duplicate_entries(config: &Config, diagnostics: &mut Vec<Diagnostic>) {
+    for (index, file) in config.files.iter().enumerate() {
+        let raw: Option<RawConfig> = std::fs::read_to_string(file).ok().and_then(|x| serde_yaml::from_str(&x).ok());
+        for (i, generator) in raw.into_iter().flat_map(|x| x.generators).enumerate() {
+            let mut keys = HashSet::new();
+            for key in generator.dict.map(|x| x.0).unwrap_or_default() {
+                if !keys.insert(key.clone()) {
+                    diagnostics.push(diagnostic(Severity::Error, format!("{}: generators[{}]", config.file_name(index), i),
+                        format!("key '{}' of group '{}' is written more than once in the dict, only the last one is used", key, generator.group)));
+                }
+            }
+        }
+    }
+}
+
+/// Namespaces that leave nothing to generate.
+fn check_namespaces(config: &Config, diagnostics: &mut Vec<Diagnostic>) {
+    let mut groups: HashMap<&str, Vec<&GeneratorMeta>> = HashMap::new();
+    for meta in config.generators.iter() {
+        groups.entry(meta.group.as_str()).or_default().push(meta);
+    }
+    let disjoint = |a: &GeneratorMeta, b: &GeneratorMeta| {
+        let mut combined = a.header();
+        combined.combine(b);
+        combined.is_empty()
+    };
+    for (i, meta) in config.generators.iter().enumerate() {
+        let warning = |message: String| diagnostic(Severity::Warning, config.generator_location(i), message);
+        // in a stable order, dicts are not ordered
+        let dict: BTreeMap<&String, _> = meta.dict.iter().flatten().collect();
+        for (key, value) in dict {
+            if let Either::Right(map) = &value.inner {
+                if map.is_empty() {
+                    diagnostics.push(warning(format!("key '{}' has no namespaced values", key)));
+                }
+                for namespace in map.keys().collect::<BTreeSet<_>>() {
+                    if disjoint(meta, &meta.header().with_namespace(namespace.to_string())) {
+                        diagnostics.push(warning(format!("namespace '{}' of key '{}' is outside of namespace '{}' of the generator, the value is never used", namespace, key, meta.namespace)));
+                    }
+                }
+            }
+        }
+        for rule in meta.rules.iter().flatten() {
+            for sub in rule.subs.iter() {
+                let members = groups.get(sub.as_str()).map(|x| x.as_slice()).unwrap_or_default();
+                if !members.is_empty() && members.iter().all(|x| disjoint(meta, x)) {
+                    diagnostics.push(warning(format!("rule '{}' never matches, namespace '{}' is disjoint with every namespace of group '{}'", rule.source, meta.namespace, sub)));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::temp_dir;
+
+    /// Diagnostics of the config `yaml` as printed, with the exit status.
+    fn run(name: &str, yaml: &str) -> (Vec<String>, i32) {
+        let dir = temp_dir(name);
+        std::fs::write(dir.join("config.yml"), yaml).unwrap();
+        let diagnostics = check(&Config::load(dir.join("config.yml")).unwrap());
+        (diagnostics.iter().map(|x| x.to_string()).collect(), exit_status(&diagnostics))
+    }
+
+    #[test]
+    fn valid_config() {
+        let yaml = r#"
+generators:
+  - group: material
+    namespace: "S:oredict."
+    completed: false
+    dict: { Iron: 铁 }
+  - group: plate
+    namespace: oredict.plate
+    rules: [{ s: "{0} Plate", t: "{0}板", subs: [material] }]
+"#;
+        assert_eq!(run("check-valid", yaml), (vec![], 0));
+    }
+
+    #[test]
+    fn rules() {
+        let yaml = r#"
+generators:
+  - group: material
+    completed: false
+    dict: { Iron: 铁 }
+  - group: plate
+    rules:
+      - { s: "Plate", t: "板", subs: [] }
+      - { s: "{0} Plate", t: "{0}板", subs: [metal] }
+      - { s: "{0} Plate", t: "{1}{0}板", subs: [material, material] }
+      - { s: "{0} {2}", t: "{0}", subs: [material] }
+"#;
+        let (diagnostics, status) = run("check-rules", yaml);
+        assert_eq!(diagnostics, vec![
+            "error: config.yml: generators[1].rules[0]: rule 'Plate' has no subs and never matches",
+            "error: config.yml: generators[1].rules[1]: rule '{0} Plate' refers to undefined group 'metal'",
+            "error: config.yml: generators[1].rules[2]: source '{0} Plate' has 2 subs but lacks {1}",
+            "error: config.yml: generators[1].rules[3]: source '{0} {2}' has 1 subs but uses {2}",
+        ]);
+        assert_eq!(status, 1);
+    }
+
+    #[test]
+    fn unused_groups_and_cycles() {
+        let yaml = r#"
+generators:
+  - group: unused
+    completed: false
+    dict: { Iron: 铁 }
+  - group: a
+    rules: [{ s: "x {0}", t: "{0}", subs: [b] }]
+  - group: b
+    completed: false
+    rules: [{ s: "y {0}", t: "{0}", subs: [a] }]
+"#;
+        let (diagnostics, status) = run("check-groups", yaml);
+        assert_eq!(diagnostics, vec![
+            "error: config.yml: generators[1]: group 'a' depends on itself: a -> b -> a",
+            "warning: config.yml: generators[0]: group 'unused' is not completed and not used by any rule, it never takes effect",
+        ]);
+        assert_eq!(status, 1);
+    }
+
+    #[test]
+    fn dict_keys() {
+        let yaml = r#"
+generators:
+  - group: material
+    namespace: "S:oredict."
+    dict: { Iron: 铁, Gold: 金 }
+  - group: material
+    namespace: oredict.
+    dict: { Iron: 铁, Gold: 黄金 }
+  - group: material
+    extensions: ["!qt"]
+    dict: { Tin: { "S:gt.": 锡 } }
+  - group: material
+    extensions: [qt]
+    dict: { Tin: { "gt.": 錫 } }
+  - group: material
+    dict:
+      Copper: 铜
+      Copper: 紫铜
+"#;
+        let (diagnostics, status) = run("check-dict", yaml);
+        assert_eq!(diagnostics, vec![
+            "error: config.yml: generators[1]: key 'Gold' of group 'material' in namespace 'oredict.' is '黄金' here but '金' at config.yml: generators[0]",
+            "error: config.yml: generators[4]: key 'Copper' of group 'material' is written more than once in the dict, only the last one is used",
+            "warning: config.yml: generators[1]: key 'Iron' of group 'material' in namespace 'oredict.' is also defined at config.yml: generators[0]",
+        ]);
+        assert_eq!(status, 1);
+    }
+
+    #[test]
+    fn namespaces() {
+        let yaml = r#"
+generators:
+  - group: material
+    namespace: "S:oredict."
+    completed: false
+    dict:
+      Iron: { "gt.": 铁, "oredict.ingot": 铁锭 }
+      Gold: {}
+  - group: plate
+    namespace: "B:oredict."
+    rules: [{ s: "{0} Plate", t: "{0}板", subs: [material] }]
+  - group: item
+    namespace: oredict.plate
+    rules: [{ s: "{0} Plate", t: "{0}板", subs: [material] }]
+"#;
+        let (diagnostics, status) = run("check-namespaces", yaml);
+        assert_eq!(diagnostics, vec![
+            "warning: config.yml: generators[0]: key 'Gold' has no namespaced values",
+            "warning: config.yml: generators[0]: namespace 'gt.' of key 'Iron' is outside of namespace 'S:oredict.' of the generator, the value is never used",
+            "warning: config.yml: generators[1]: rule '{0} Plate' never matches, namespace 'B:oredict.' is disjoint with every namespace of group 'material'",
+        ]);
+        assert_eq!(status, 0);
+    }
+}
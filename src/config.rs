@@ -1,17 +1,41 @@
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::ffi::OsString;
-use std::path::Path;
-use clap::{Parser};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use clap::{Parser, Subcommand};
 use either::Either;
 use serde::{Serialize, Deserialize};
-use crate::generator::{DictGenerator, Generator, RuleGenerator};
-use crate::lang::LangResult;
+use crate::format::LangFormat;
+use crate::generator::{DictGenerator, GeneratorType, RuleGenerator};
 use crate::meta::GeneratorMeta;
+use crate::output::Output;
+use crate::xliff::XliffVersion;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
+    #[serde(default)]
     pub version: String,
+    #[serde(default)]
     pub lang: String,
+    /// Config files and globs, relative to this file, whose generators come before the ones of this file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    #[serde(default)]
     pub generators: Vec<GeneratorMeta>,
+    /// Files read by `Config::load`, the loaded one first
+    #[serde(skip)]
+    pub files: Vec<PathBuf>,
+    /// Index into `files` and index within that file of every generator
+    #[serde(skip)]
+    pub generator_sources: Vec<(usize, usize)>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Config {
@@ -19,93 +43,152 @@ impl Config {
         Config {
             lang: String::from("zh"),
             version: String::new(),
+            include: vec![],
             generators: vec![],
+            files: vec![],
+            generator_sources: vec![],
         }
     }
 
-    pub fn generators(&self) {
-        let mut list: Vec<Box<dyn Generator>> = vec![];
-        for meta in self.generators.iter() {
-            if let Some(rules) = &meta.rules {
-                // RuleGenerator
-                list.push(Box::new(RuleGenerator {
-                    meta, rules
-                }));
-            } else if let Some(dict) = &meta.dict {
+    /// Reads the config at `path` along with every file it includes.
+    ///
+    /// Version and language come from `path`, included files only add generators.
+    /// A file included again is skipped, including a file from itself is an error.
+    pub fn load<P>(path: P) -> std::io::Result<Config> where P: AsRef<Path> {
+        let mut files = vec![];
+        let mut config = Self::load_included(path.as_ref(), &mut vec![], &mut HashSet::new(), &mut files)?;
+        config.files = files;
+        Ok(config)
+    }
+
+    fn load_included(path: &Path, including: &mut Vec<PathBuf>, loaded: &mut HashSet<PathBuf>, files: &mut Vec<PathBuf>) -> std::io::Result<Config> {
+        let with_path = |e: std::io::Error| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e));
+        let canonical = fs::canonicalize(path).map_err(with_path)?;
+        let text = fs::read_to_string(path).map_err(with_path)?;
+        let mut config: Config = serde_yaml::from_str(&text)
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?;
+        including.push(canonical.clone());
+        loaded.insert(canonical);
+        let file = files.len();
+        files.push(path.to_path_buf());
+
+        let mut generators = vec![];
+        let mut sources = vec![];
+        for pattern in config.include.iter() {
+            let error = |message: String| {
+                // the line of the include entry, as far as it can be told from the text
+                let line = text.lines().position(|x| x.contains(pattern.as_str())).map_or(0, |x| x + 1);
+                std::io::Error::new(ErrorKind::InvalidData, format!("{}: line {}: {}", path.display(), line, message))
+            };
+            for included in resolve_include(path, pattern).map_err(error)? {
+                let canonical = fs::canonicalize(&included).map_err(|e| error(format!("{}: {}", included.display(), e)))?;
+                if including.contains(&canonical) {
+                    return Err(error(format!("{} includes itself", included.display())));
+                }
+                if loaded.contains(&canonical) {
+                    continue;
+                }
+                let included = Self::load_included(&included, including, loaded, files)?;
+                generators.extend(included.generators);
+                sources.extend(included.generator_sources);
+            }
+        }
+        including.pop();
+
+        sources.extend((0..config.generators.len()).map(|i| (file, i)));
+        generators.append(&mut config.generators);
+        config.generators = generators;
+        config.generator_sources = sources;
+        Ok(config)
+    }
+
+    /// Path of the file at `file` in `files` relative to the folder of the loaded config,
+    /// like `materials/metals.yml`, so that it does not depend on where gt6tg is run.
+    pub fn file_name(&self, file: usize) -> String {
+        let path = &self.files[file];
+        let base = self.files[0].parent().unwrap_or_else(|| Path::new(""));
+        path.strip_prefix(base).unwrap_or(path).display().to_string()
+    }
+
+    /// Where the generator at `index` is defined, like `config.yml: generators[3]`.
+    pub fn generator_location(&self, index: usize) -> String {
+        match self.generator_sources.get(index) {
+            Some((file, i)) => format!("{}: generators[{}]", self.file_name(*file), i),
+            None => format!("generators[{}]", index),
+        }
+    }
+
+    /// Every extension referred to by a generator, without the `!` of excluded ones.
+    pub fn extensions(&self) -> BTreeSet<&str> {
+        self.generators.iter()
+            .flat_map(|x| x.extensions.iter())
+            .map(|x| x.strip_prefix('!').unwrap_or(x))
+            .collect()
+    }
+
+    pub fn generators(&self) -> Vec<GeneratorType<'_>> {
+        let mut list: Vec<GeneratorType<'_>> = vec![];
+        for (index, meta) in self.generators.iter().enumerate() {
+            if let Some(dict) = &meta.dict {
+                // DictGenerator
+                let mut plain = HashMap::new();
                 for (key, value) in dict.iter() {
                     if let Either::Left(str) = &value.inner {
-                        list.push(Box::new(DictGenerator {
-                            meta, dict: vec![(key.to_string(), str.to_string())]
-                        }));
-
-                        /*
-                        if (dict_item.is_map()) {
-
-                } else {
-                    // normal dict
-                    if (!gen) {
-                        gen = std::make_shared<DictGenerator>(meta);
-                        gen->dict() = std::make_shared<LangList>();
-                    }
-                    auto &dict = *(gen->dict());
-                    dict.emplace_back(_csubstr2str(dict_item.key()),
-                                      _csubstr2str(dict_item.val()));
-                }*/
+                        plain.insert(key.to_string(), str.to_string());
                     } else if let Either::Right(map) = &value.inner {
-                        for (child_key, child_value) in map.iter() {
-                            let mut dict = vec![];
-                            dict.push((key.to_string(), child_value.to_string()));
-                            list.push(Box::new(DictGenerator {
-                                meta, dict
+                        // NSGeneratorMeta: one generator per namespace
+                        for (namespace, child_value) in map.iter() {
+                            let mut ns_meta = meta.header();
+                            ns_meta.combine(&meta.header().with_namespace(namespace.to_string()));
+                            if ns_meta.is_empty() {
+                                // disjoint with the namespace of the generator
+                                continue;
+                            }
+                            let dict = HashMap::from([(key.to_string(), child_value.to_string())]);
+                            list.push(GeneratorType::Dict(DictGenerator {
+                                index, meta: Cow::Owned(ns_meta), dict
                             }));
                         }
-                        /*
-                        for (auto dict_item_children : dict_item.children()) {
-                        auto local_gen = std::make_shared<DictGenerator>(
-                            std::make_shared<NSGeneratorMeta>(
-                                _csubstr2str(dict_item_children.key()), meta));
-                        local_gen->dict() = std::make_shared<LangList>();
-                        auto &dict = *(local_gen->dict());
-                        dict.emplace_back(_csubstr2str(dict_item.key()),
-                                          _csubstr2str(dict_item_children.val()));
-                        result.push_back(std::move(local_gen));
-                    }*/
                     }
                 }
+                if !plain.is_empty() {
+                    list.push(GeneratorType::Dict(DictGenerator {
+                        index, meta: Cow::Borrowed(meta), dict: plain
+                    }));
+                }
+            }
+            if let Some(rules) = &meta.rules {
+                // RuleGenerator
+                list.push(GeneratorType::Rule(RuleGenerator::new(index, meta, rules)));
             }
         }
+        list
     }
-/*
-// DictGenerator
-        if (child.is_map()) {
-            shared_ptr<DictGenerator> gen = nullptr;
-            for (auto dict_item : child.children()) {
+}
 
-            }
-            if (gen) {
-                // final commit
-                result.push_back(std::move(gen));
-            }
-        } else {
-            throw std::invalid_argument("invalid dict for generator");
-        }
-    } else if (ckey == "rules") {
-    // RuleGenerator
-    if (child.is_seq()) {
-    auto gen = std::make_shared<RuleGenerator>(meta);
-    auto &rules = gen->rules();
-    for (auto rule : child.children()) {
-    rules.push_back(parse_rule(rule));
-    }
-    result.push_back(std::move(gen));
-    } else {
-    throw std::invalid_argument("invalid rules for generator");
+/// Files included by `pattern` of the config at `path`, in order. A glob must match at least one file.
+fn resolve_include(path: &Path, pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    if !pattern.contains(['*', '?', '[']) {
+        return Ok(vec![base.join(pattern)]);
     }
+    let full = if base.as_os_str().is_empty() {
+        pattern.to_string()
+    } else {
+        format!("{}/{}", glob::Pattern::escape(&base.to_string_lossy()), pattern)
+    };
+    let files = glob::glob(&full)
+        .map_err(|e| format!("invalid include '{}': {}", pattern, e))?
+        .collect::<Result<Vec<PathBuf>, _>>()
+        .map_err(|e| format!("include '{}': {}", pattern, e))?;
+    let files: Vec<PathBuf> = files.into_iter().filter(|x| x.is_file()).collect();
+    if files.is_empty() {
+        return Err(format!("include '{}' matches no file", pattern));
     }
-    }*/
+    Ok(files)
 }
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[clap(name = "gt6tg")]
 #[clap(about = "GregTech 6 Translation Groupware")]
 pub struct RuntimeOptions {
@@ -125,6 +208,26 @@ pub struct RuntimeOptions {
     #[clap(long = "extra_target")]
     pub extra_target_path: Option<OsString>,
 
+    /// report of the keys both generated and in the fallback, JSON for .json files and YAML otherwise
+    #[clap(long = "conflict-report")]
+    pub conflict_report_path: Option<OsString>,
+
+    /// report of the keys generated differently by equally specific generators, JSON for .json files and YAML otherwise
+    #[clap(long = "ambiguity-report")]
+    pub ambiguity_report_path: Option<OsString>,
+
+    /// sidecar attributing every output key to its generator, rule and subs, or to the fallback, as JSON Lines
+    #[clap(long = "provenance")]
+    pub provenance_path: Option<OsString>,
+
+    /// format of the source files, detected from their extension and content by default
+    #[clap(long = "source-format", arg_enum)]
+    pub source_format: Option<LangFormat>,
+
+    /// format of the target files, following their extension and the source by default
+    #[clap(long = "target-format", arg_enum)]
+    pub target_format: Option<LangFormat>,
+
     /// config file
     #[clap(short, long = "config")]
     pub config_path: Option<OsString>,
@@ -137,14 +240,115 @@ pub struct RuntimeOptions {
     #[clap(short, long = "language", default_value = "zh")]
     pub lang: String,
 
+    /// enabled extensions, generators requiring other extensions are skipped
     #[clap(short, long = "extensions")]
     pub extensions: Vec<OsString>,
 
     #[clap(short, long = "remove", parse(try_from_str), default_value_t = false)]
-    pub remove_redundant_fallback: bool
+    pub remove_redundant_fallback: bool,
+
+    /// report what would change without writing any file
+    #[clap(long = "dry-run")]
+    pub dry_run: bool,
+
+    /// number of backups kept of every overwritten file
+    #[clap(long = "backups", default_value_t = 0)]
+    pub backups: usize,
+
+    /// generate everything again, neither reading nor writing the cache of cached generators
+    #[clap(long = "no-cache")]
+    pub no_cache: bool,
+
+    /// only recompute the entries affected by changes since the last incremental run
+    #[clap(long = "incremental")]
+    pub incremental: bool,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Commands other than replacing, which is run when no command is given
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// List the extensions declared in the config
+    Extensions,
+    /// Check the config for mistakes, exiting with 1 if there is an error
+    Check,
+    /// Print the dependencies between groups as Graphviz DOT
+    Graph {
+        /// only keep this group, the groups depending on it and the groups it depends on
+        #[clap(long)]
+        group: Option<String>,
+        /// output file instead of the standard output
+        #[clap(short, long)]
+        output: Option<OsString>,
+    },
+    /// Show how the entry of a key is translated, with every candidate considered
+    Explain {
+        /// key as written in the main source, or its name like oredict.plateIron.name
+        key: String,
+    },
+    /// Export the unknown entries as a gettext template
+    PoExport {
+        /// also export the fallback entries with their translations, as a PO file
+        #[clap(long)]
+        fallback: bool,
+        /// output file, GregTech.pot or GregTech.po in the folder of the language by default
+        #[clap(short, long)]
+        output: Option<OsString>,
+    },
+    /// Merge translated PO files into the fallback
+    PoImport {
+        /// PO files to import
+        #[clap(required = true)]
+        files: Vec<OsString>,
+    },
+    /// Export the outcome of replacing as XLIFF
+    XliffExport {
+        /// XLIFF version
+        #[clap(long = "xliff-version", arg_enum, default_value = "1.2")]
+        version: XliffVersion,
+        /// output file, GregTech.xlf in the folder of the language by default
+        #[clap(short, long)]
+        output: Option<OsString>,
+    },
+    /// Merge translated XLIFF files of either version into the fallback
+    XliffImport {
+        /// XLIFF files to import
+        #[clap(required = true)]
+        files: Vec<OsString>,
+    },
+    /// Export the outcome of replacing as a review sheet, TSV for .tsv files and CSV otherwise
+    SheetExport {
+        /// output file, GregTech.review.csv in the folder of the language by default
+        #[clap(short, long)]
+        output: Option<OsString>,
+    },
+    /// Merge edited review sheets into the fallback
+    SheetImport {
+        /// CSV or TSV files to import
+        #[clap(required = true)]
+        files: Vec<OsString>,
+    },
 }
 
 impl RuntimeOptions {
+    pub fn output(&self) -> Output {
+        Output {
+            dry_run: self.dry_run,
+            backups: self.backups,
+        }
+    }
+
+    /// Format of the target file at `path` written from a source in `source` format.
+    pub fn target_format(&self, path: &Path, source: LangFormat) -> LangFormat {
+        self.target_format.unwrap_or_else(|| LangFormat::for_target(path, source))
+    }
+
+    pub fn enabled_extensions(&self) -> HashSet<String> {
+        self.extensions.iter().map(|x| x.to_string_lossy().into_owned()).collect()
+    }
+
     pub fn determine_paths(self) -> Self {
         let mut this = self;
         if this.main_source_path.is_none() {
@@ -165,4 +369,74 @@ impl RuntimeOptions {
 
         this
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::temp_dir;
+
+    fn write(dir: &Path, name: &str, text: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn groups(config: &Config) -> Vec<&str> {
+        config.generators.iter().map(|x| x.group.as_str()).collect()
+    }
+
+    #[test]
+    fn includes_are_relative_to_their_file() {
+        let dir = temp_dir("include-relative");
+        write(&dir, "config.yml", "lang: zh\ninclude: [materials/metals.yml]\ngenerators: [{ group: main }]\n");
+        write(&dir, "materials/metals.yml", "include: [../shared.yml]\ngenerators: [{ group: metal }]\n");
+        write(&dir, "shared.yml", "lang: en\ngenerators: [{ group: shared }, { group: shared2 }]\n");
+        let config = Config::load(dir.join("config.yml")).unwrap();
+        assert_eq!(config.lang, "zh");
+        assert_eq!(groups(&config), vec!["shared", "shared2", "metal", "main"]);
+        assert_eq!(config.generator_location(1), "materials/../shared.yml: generators[1]");
+        assert_eq!(config.generator_location(2), "materials/metals.yml: generators[0]");
+        assert_eq!(config.generator_location(3), "config.yml: generators[0]");
+    }
+
+    #[test]
+    fn globs_include_files_in_order_once() {
+        let dir = temp_dir("include-glob");
+        write(&dir, "config.yml", "include: [b.yml, 'parts/*.yml']\ngenerators: [{ group: main }]\n");
+        write(&dir, "b.yml", "generators: [{ group: b }]\n");
+        write(&dir, "parts/2.yml", "include: [../b.yml]\ngenerators: [{ group: two }]\n");
+        write(&dir, "parts/1.yml", "generators: [{ group: one }]\n");
+        write(&dir, "parts/notes.txt", "not included");
+        let config = Config::load(dir.join("config.yml")).unwrap();
+        assert_eq!(groups(&config), vec!["b", "one", "two", "main"]);
+        assert_eq!(config.files.len(), 4);
+    }
+
+    #[test]
+    fn include_loops_are_errors() {
+        let dir = temp_dir("include-loop");
+        write(&dir, "config.yml", "include: [a.yml]\n");
+        write(&dir, "a.yml", "# a\ninclude:\n  - b.yml\n");
+        write(&dir, "b.yml", "include:\n  - a.yml\n");
+        let error = Config::load(dir.join("config.yml")).unwrap_err().to_string();
+        assert!(error.contains("b.yml: line 2: ") && error.ends_with("a.yml includes itself"), "{}", error);
+        write(&dir, "config.yml", "include: [config.yml]\n");
+        assert!(Config::load(dir.join("config.yml")).is_err());
+    }
+
+    #[test]
+    fn include_errors_have_locations() {
+        let dir = temp_dir("include-error");
+        write(&dir, "config.yml", "version: 1\ninclude:\n  - 'missing/*.yml'\n");
+        let error = Config::load(dir.join("config.yml")).unwrap_err().to_string();
+        assert!(error.ends_with("config.yml: line 3: include 'missing/*.yml' matches no file"), "{}", error);
+        write(&dir, "config.yml", "include: [bad.yml]\n");
+        write(&dir, "bad.yml", "generators:\n  - group: [\n");
+        let error = Config::load(dir.join("config.yml")).unwrap_err().to_string();
+        assert!(error.contains("bad.yml: ") && error.contains("line 3"), "{}", error);
+        write(&dir, "config.yml", "include: [gone.yml]\n");
+        let error = Config::load(dir.join("config.yml")).unwrap_err().to_string();
+        assert!(error.contains("config.yml: line 1: ") && error.contains("gone.yml"), "{}", error);
+    }
+}
@@ -0,0 +1,117 @@
+//! Merging translations made in other tools back into the fallback.
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io::ErrorKind;
+use std::path::Path;
+use crate::config::RuntimeOptions;
+use crate::forge::ParseError;
+use crate::format::LangFormat;
+use crate::lang::LangFile;
+
+/// A translated entry read from an exchange file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportEntry {
+    /// Where the entry was read, like `file:line`
+    pub position: String,
+    /// Key in the form of the main source
+    pub key: Option<String>,
+    /// English text the translation was made from
+    pub source: String,
+    pub target: String,
+    /// Marked as needing review
+    pub fuzzy: bool,
+    /// Marked as no longer used
+    pub obsolete: bool,
+    /// Edited although generated, which only the config can change
+    pub generated: bool,
+}
+
+/// What an import did, entries are listed as `position: key`.
+#[derive(Debug, Clone, Default)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub unchanged: usize,
+    pub untranslated: usize,
+    /// Marked fuzzy, or translated from an English text that has changed since
+    pub fuzzy: Vec<String>,
+    /// Marked obsolete, without a key, or with a key no longer in the English source
+    pub obsolete: Vec<String>,
+    /// Generated entries edited, the config has to be changed instead
+    pub generated: Vec<String>,
+}
+
+impl Display for ImportReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "imported {}, unchanged {}, untranslated {}", self.imported, self.unchanged, self.untranslated)?;
+        for (title, list) in [("fuzzy", &self.fuzzy), ("obsolete", &self.obsolete), ("generated", &self.generated)] {
+            if !list.is_empty() {
+                writeln!(f, "{} {}, not imported:", list.len(), title)?;
+                for item in list.iter() {
+                    writeln!(f, "    {}", item)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn invalid_data(path: &Path, e: ParseError) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+}
+
+/// Merges `entries` into the fallback, which is created if missing.
+///
+/// Existing entries are updated in place and new ones appended, only entries
+/// translated from the current English text are taken.
+pub fn import<I>(options: &RuntimeOptions, entries: I) -> std::io::Result<ImportReport> where I: IntoIterator<Item = ImportEntry> {
+    let main_source = LangFile::read_as(options.main_source_path.as_ref().unwrap(), options.source_format)?;
+    let main_format = main_source.format;
+    let english: HashMap<&str, &str> = main_source.items.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+
+    let fallback_path = Path::new(options.extra_source_path.as_ref().expect("No fallback path"));
+    let mut fallback = if fallback_path.exists() {
+        LangFile::read_as(fallback_path, options.source_format)?
+    } else {
+        main_source.empty_like().into_format(LangFormat::for_target(fallback_path, main_format))
+    };
+    let fallback_format = fallback.format;
+    let mut positions: HashMap<String, usize> = fallback.items.iter().enumerate()
+        .map(|(i, (k, _))| (fallback_format.convert_key(k, main_format), i))
+        .collect();
+
+    let mut report = ImportReport::default();
+    for entry in entries {
+        let describe = |key: &str| format!("{}: {}", entry.position, key);
+        let key = match &entry.key {
+            Some(key) if !entry.obsolete && english.contains_key(key.as_str()) => key,
+            _ => {
+                report.obsolete.push(describe(entry.key.as_deref().unwrap_or(&entry.source)));
+                continue;
+            }
+        };
+        if entry.generated {
+            report.generated.push(format!("{} (change the config)", describe(key)));
+        } else if entry.target.is_empty() {
+            report.untranslated += 1;
+        } else if entry.fuzzy {
+            report.fuzzy.push(describe(key));
+        } else if english[key.as_str()] != entry.source {
+            report.fuzzy.push(format!("{} (English changed)", describe(key)));
+        } else {
+            match positions.get(key) {
+                Some(i) if fallback.items[*i].1 == entry.target => report.unchanged += 1,
+                Some(i) => {
+                    fallback.items[*i].1 = entry.target;
+                    report.imported += 1;
+                }
+                None => {
+                    positions.insert(key.clone(), fallback.items.len());
+                    fallback.items.push((main_format.convert_key(key, fallback_format), entry.target));
+                    report.imported += 1;
+                }
+            }
+        }
+    }
+    options.output().write(fallback_path, &fallback.to_bytes())?;
+    Ok(report)
+}
@@ -0,0 +1,94 @@
+//! How the entry of one key is translated, reported by `gt6tg explain`.
+use std::fmt::Write;
+use std::io::ErrorKind;
+use crate::config::{Config, RuntimeOptions};
+use crate::forge;
+use crate::replacer::{extra_dict, judge, Replacer};
+use crate::report::location;
+use crate::result::{Decision, Origin, Provenance, Reason, SubProvenance};
+use crate::rule::Rule;
+
+/// The rule at `rule` of the generator at `generator`.
+fn rule(config: &Config, generator: usize, rule: Option<usize>) -> Option<&Rule> {
+    config.generators.get(generator)?.rules.as_ref()?.get(rule?)
+}
+
+/// Writes the results matched by placeholders and those matched by their own, indented by `indent`.
+fn write_subs(output: &mut String, config: &Config, subs: &[SubProvenance], indent: usize) {
+    for (i, sub) in subs.iter().enumerate() {
+        let namespace = if sub.namespace.is_empty() { String::new() } else { format!(", namespace '{}'", sub.namespace) };
+        let _ = writeln!(output, "{:indent$}{{{}}} {:?} -> {:?} from group '{}'{}, {}",
+            "", i, sub.source, sub.target, sub.group, namespace, location(config, sub.generator, sub.rule), indent = indent);
+        if let Some(sub_rule) = rule(config, sub.generator, sub.rule) {
+            let _ = writeln!(output, "{:indent$}rule {:?} -> {:?}", "", sub_rule.source, sub_rule.target, indent = indent + 4);
+            write_subs(output, config, &sub.subs, indent + 6);
+        }
+    }
+}
+
+/// Every candidate for the entry of `key` in the main source, which one is written and why.
+///
+/// `key` is either the key as written in the main source or its name, like `oredict.plateIron.name`.
+pub fn explain(config: &Config, options: &RuntimeOptions, key: &str) -> std::io::Result<String> {
+    let generators = config.generators();
+    let replacer = Replacer::new(config, options.clone(), generators.iter().collect())?;
+    let (main_source, extra_source) = replacer.read_sources()?;
+    let (key, source) = main_source.items.iter()
+        .find(|(x, _)| x == key)
+        .or_else(|| main_source.items.iter().find(|(x, _)| forge::key_name(x) == forge::key_name(key)))
+        .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidInput, format!("no key '{}' in the main source", key)))?;
+    let fallback = extra_dict(main_source.format, extra_source.as_ref()).get(key.as_str()).map(|x| x.to_string());
+
+    let mut output = String::new();
+    let _ = writeln!(output, "key: {}", key);
+    let _ = writeln!(output, "source: {:?}", source);
+    match fallback.as_ref() {
+        Some(x) => { let _ = writeln!(output, "fallback: {:?}", x); }
+        None => { let _ = writeln!(output, "fallback: none"); }
+    }
+
+    let candidates: Vec<Provenance> = replacer.generate_map([source.as_str()]).remove(source).unwrap_or_default();
+    if candidates.is_empty() {
+        let _ = writeln!(output, "candidates: none");
+    } else {
+        let _ = writeln!(output, "candidates:");
+    }
+    for (i, candidate) in candidates.iter().enumerate() {
+        let _ = writeln!(output, "  [{}] {:?}", i + 1, candidate.target);
+        let mut scope = format!("group '{}', namespace '{}', priority {}", candidate.group, candidate.namespace, candidate.priority);
+        if !forge::in_namespace(key, &candidate.namespace) {
+            scope.push_str(", namespace does not cover the key");
+        }
+        let _ = writeln!(output, "      {}", scope);
+        let _ = writeln!(output, "      at {}", location(config, candidate.generator, candidate.rule));
+        if let Some(rule) = rule(config, candidate.generator, candidate.rule) {
+            let _ = writeln!(output, "      rule {:?} -> {:?}", rule.source, rule.target);
+            write_subs(&mut output, config, &candidate.subs, 8);
+        }
+    }
+
+    let (translation, decision) = judge(key, source, Some(&candidates), fallback.as_deref());
+    let reason = match decision {
+        Some(Decision { winner, reason }) => {
+            let index = winner + 1;
+            let mut reason = match reason {
+                Reason::Only => format!("candidate [{}], the only one covering the key", index),
+                Reason::MostSpecific { covering } => format!("candidate [{}], the most specific namespace of {} covering the key", index, covering),
+                Reason::Priority { specific } => format!("candidate [{}], the highest priority of {} as specific", index, specific),
+                Reason::ConfigOrder { tied } => format!("candidate [{}], the last in config order of {} tied", index, tied),
+            };
+            if !translation.rivals.is_empty() {
+                reason.push_str(&format!(", ambiguous with {} other targets", translation.rivals.len()));
+            }
+            if translation.origin == Origin::Conflict {
+                reason.push_str(", overriding the fallback");
+            }
+            reason
+        }
+        None if translation.origin == Origin::Fallback => "no candidate covers the key, the fallback is used".to_string(),
+        None => "no candidate covers the key and there is no fallback, the source is kept".to_string(),
+    };
+    let origin = format!("{:?}", translation.origin).to_uppercase();
+    let _ = writeln!(output, "result: {:?} ({}: {})", translation.target, origin, reason);
+    Ok(output)
+}
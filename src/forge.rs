@@ -0,0 +1,518 @@
+//! Reader for the Forge configuration format wrapping GregTech.lang:
+//!
+//! ```plain
+//! # Configuration file
+//! enablelangfile {
+//!     B:UseThisFileAsLanguageFile=true
+//! }
+//!
+//! languagefile {
+//!     S:"Dirty Water.name"=污水
+//! }
+//! ```
+//!
+//! Keys are kept as written, type prefix and quotes included, namespaces are
+//! matched against their names, see [`in_namespace`]. Values run verbatim to
+//! the end of the line.
+//!
+//! Everything else in the file is kept in a [`Layout`] so that writing the
+//! entries back reproduces the original file except for the edited lines.
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use crate::lang::LangItem;
+
+/// The category holding the lang entries.
+pub const LANG_CATEGORY: &str = "languagefile";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// 1-based line number
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line<'t> {
+    Blank,
+    Comment(&'t str),
+    CategoryStart(String),
+    CategoryEnd,
+    Entry { key: &'t str, value: &'t str },
+    ListStart { key: &'t str },
+    ListItem(&'t str),
+    ListEnd,
+}
+
+/// Splits `text` into raw and classified lines, checking that categories and lists are balanced.
+pub fn lines(text: &str) -> Result<Vec<(&str, Line<'_>)>, ParseError> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut result = vec![];
+    let mut categories: Vec<(usize, String)> = vec![];
+    let mut list: Option<usize> = None;
+    for (i, raw) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let line = raw.trim_start();
+        let error = |message: String| ParseError { line: line_no, message };
+
+        if list.is_some() {
+            if line.trim_end() == ">" {
+                list = None;
+                result.push((raw, Line::ListEnd));
+            } else {
+                result.push((raw, Line::ListItem(line.trim_end())));
+            }
+            continue;
+        }
+
+        let trimmed = line.trim_end();
+        let parsed = if trimmed.is_empty() {
+            Line::Blank
+        } else if let Some(comment) = trimmed.strip_prefix('#') {
+            Line::Comment(comment)
+        } else if trimmed == "}" {
+            if categories.pop().is_none() {
+                return Err(error("unexpected '}' outside of a category".to_string()));
+            }
+            Line::CategoryEnd
+        } else if is_typed(line) {
+            let key_len = key_length(line).map_err(error)?;
+            let (key, rest) = line.split_at(key_len);
+            if let Some(value) = rest.trim_start().strip_prefix('=') {
+                Line::Entry { key, value: value.trim_end_matches('\r') }
+            } else if rest.trim() == "<" {
+                list = Some(line_no);
+                Line::ListStart { key }
+            } else {
+                return Err(error(format!("expected '=' after key {}", key)));
+            }
+        } else if let Some(name) = trimmed.strip_suffix('{') {
+            let name = unquote(name.trim()).map_err(error)?;
+            categories.push((line_no, name.clone()));
+            Line::CategoryStart(name)
+        } else {
+            return Err(error(format!("expected a category, a typed key like S:name or a comment, found '{}'", trimmed)));
+        };
+        result.push((raw, parsed));
+    }
+    if let Some(line) = list {
+        return Err(ParseError { line, message: "unclosed list".to_string() });
+    }
+    if let Some((line, name)) = categories.pop() {
+        return Err(ParseError { line, message: format!("unclosed category '{}'", name) });
+    }
+    Ok(result)
+}
+
+/// Reads the entries of the `languagefile` category along with the layout of the file.
+pub fn parse(text: &str) -> Result<(Vec<LangItem>, Layout), ParseError> {
+    let mut items = vec![];
+    let mut layout = Layout::empty_like(text);
+    let mut categories: Vec<String> = vec![];
+    for (raw, line) in lines(text)? {
+        let in_lang = categories.len() == 1 && categories[0] == LANG_CATEGORY;
+        let layout_line = match line {
+            Line::CategoryStart(name) => {
+                categories.push(name);
+                LayoutLine::Raw(raw.to_string())
+            }
+            Line::CategoryEnd => {
+                categories.pop();
+                if in_lang {
+                    LayoutLine::LangEnd(raw.to_string())
+                } else {
+                    LayoutLine::Raw(raw.to_string())
+                }
+            }
+            Line::Entry { key, value } if in_lang => {
+                let key_start = offset(raw, key);
+                let value_start = offset(raw, value);
+                items.push((key.to_string(), value.to_string()));
+                LayoutLine::Entry {
+                    indent: raw[..key_start].to_string(),
+                    key: key.to_string(),
+                    separator: raw[key_start + key.len()..value_start].to_string(),
+                }
+            }
+            _ => LayoutLine::Raw(raw.to_string()),
+        };
+        layout.lines.push(layout_line);
+    }
+    Ok((items, layout))
+}
+
+/// Byte offset of `inner` within `outer`, which must be a sub-slice of it.
+fn offset(outer: &str, inner: &str) -> usize {
+    inner.as_ptr() as usize - outer.as_ptr() as usize
+}
+
+/// Everything of a lang file except the values of its entries.
+///
+/// Also used for plain `key=value` files, which have no `languagefile` category.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    pub(crate) bom: bool,
+    pub(crate) newline: String,
+    pub(crate) final_newline: bool,
+    /// Whether entries live in a `languagefile` category, created when missing
+    pub(crate) lang_category: bool,
+    pub(crate) lines: Vec<LayoutLine>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum LayoutLine {
+    /// Kept verbatim
+    Raw(String),
+    /// An entry of the `languagefile` category, its value comes from the written items
+    Entry { indent: String, key: String, separator: String },
+    /// The closing brace of the `languagefile` category, new entries are inserted before it
+    LangEnd(String),
+}
+
+impl Default for Layout {
+    /// The layout written by GregTech itself.
+    fn default() -> Self {
+        let raw = |x: &str| LayoutLine::Raw(x.to_string());
+        Layout {
+            bom: false,
+            newline: "\n".to_string(),
+            final_newline: true,
+            lang_category: true,
+            lines: vec![
+                raw("# Configuration file"),
+                raw("enablelangfile {"),
+                raw("    B:UseThisFileAsLanguageFile=true"),
+                raw("}"),
+                raw(""),
+                raw("languagefile {"),
+                LayoutLine::LangEnd("}".to_string()),
+                raw(""),
+            ],
+        }
+    }
+}
+
+impl Layout {
+    /// A layout without lines, keeping the BOM and newlines of `text`.
+    pub(crate) fn empty_like(text: &str) -> Self {
+        Layout {
+            bom: text.starts_with('\u{feff}'),
+            newline: if text.contains("\r\n") { "\r\n" } else { "\n" }.to_string(),
+            final_newline: text.ends_with('\n') || text.is_empty(),
+            lang_category: true,
+            lines: vec![],
+        }
+    }
+
+    /// Writes `items` in this layout.
+    ///
+    /// Entries keep their line and formatting, entries missing from `items`
+    /// are dropped and new ones are appended to the `languagefile` category.
+    pub fn write<W: Write>(&self, writer: &mut W, items: &[LangItem]) -> std::io::Result<()> {
+        let mut pending: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (i, (key, _)) in items.iter().enumerate().rev() {
+            pending.entry(key.as_str()).or_default().push(i);
+        }
+        let mut written = vec![false; items.len()];
+        // entries outside of a category are not indented
+        let mut indent = if self.lang_category { "    " } else { "" };
+        let mut output: Vec<String> = vec![];
+
+        let append_rest = |output: &mut Vec<String>, written: &mut Vec<bool>, indent: &str| {
+            for (i, (key, value)) in items.iter().enumerate() {
+                if !written[i] {
+                    written[i] = true;
+                    output.push(format!("{}{}={}", indent, key, value));
+                }
+            }
+        };
+
+        for line in self.lines.iter() {
+            match line {
+                LayoutLine::Raw(raw) => output.push(raw.clone()),
+                LayoutLine::Entry { indent: entry_indent, key, separator } => {
+                    indent = entry_indent;
+                    if let Some(i) = pending.get_mut(key.as_str()).and_then(|x| x.pop()) {
+                        written[i] = true;
+                        output.push(format!("{}{}{}{}", entry_indent, key, separator, items[i].1));
+                    }
+                }
+                LayoutLine::LangEnd(raw) => {
+                    append_rest(&mut output, &mut written, indent);
+                    output.push(raw.clone());
+                }
+            }
+        }
+        if !self.lang_category {
+            append_rest(&mut output, &mut written, indent);
+        } else if written.iter().any(|x| !x) {
+            // no languagefile category in the layout
+            output.push(format!("{} {{", LANG_CATEGORY));
+            append_rest(&mut output, &mut written, indent);
+            output.push("}".to_string());
+        }
+
+        if self.bom {
+            write!(writer, "\u{feff}")?;
+        }
+        write!(writer, "{}", output.join(&self.newline))?;
+        if self.final_newline {
+            write!(writer, "{}", self.newline)?;
+        }
+        Ok(())
+    }
+}
+
+/// Name of a typed key, `S:"Dirty Water.name"` -> `Dirty Water.name`.
+pub fn key_name(key: &str) -> String {
+    if !is_typed(key) {
+        return key.to_string();
+    }
+    unquote(&key[2..]).unwrap_or_else(|_| key[2..].to_string())
+}
+
+/// Name part of a namespace, the start of the key names it covers:
+/// `S:oredict.` -> `oredict.`, `S:"Dirty W` -> `Dirty W`.
+pub fn namespace_name(namespace: &str) -> String {
+    if !is_typed(namespace) {
+        return namespace.to_string();
+    }
+    let name = &namespace[2..];
+    let Some(quoted) = name.strip_prefix('"') else { return name.to_string() };
+    let mut result = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.extend(chars.next()),
+            '"' => break,
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Whether `key` falls within `namespace`, either in any form of key.
+///
+/// Both are compared by name, so `S:oredict.` covers `S:oredict.ingotIron.name` as well as
+/// `oredict.ingotIron.name` of plain and JSON files. Keys of another type are never covered.
+pub fn in_namespace(key: &str, namespace: &str) -> bool {
+    if key.starts_with(namespace) {
+        return true;
+    }
+    if is_typed(key) && is_typed(namespace) && key[..2] != namespace[..2] {
+        return false;
+    }
+    key_name(key).starts_with(namespace_name(namespace).as_str())
+}
+
+/// Namespace covering the keys covered by both `a` and `b`, `None` if they are disjoint.
+///
+/// Namespaces are compared by name, the one with the longer name is kept and
+/// typed as the other if only that is typed, so `oredict.plate` and `S:oredict.`
+/// make `S:oredict.plate`. Namespaces of different types are disjoint.
+pub fn namespace_intersection(a: &str, b: &str) -> Option<String> {
+    let namespace_type = |x: &str| if is_typed(x) { Some(x[..2].to_string()) } else { None };
+    let (a_type, b_type) = (namespace_type(a), namespace_type(b));
+    if a_type.is_some() && b_type.is_some() && a_type != b_type {
+        return None;
+    }
+    let (a_name, b_name) = (namespace_name(a), namespace_name(b));
+    let (longer, name) = if a_name.starts_with(b_name.as_str()) {
+        (a, a_name)
+    } else if b_name.starts_with(a_name.as_str()) {
+        (b, b_name)
+    } else {
+        return None;
+    };
+    match a_type.or(b_type) {
+        Some(prefix) if !is_typed(longer) => {
+            // a namespace is the start of a key, so a quoted name is left open
+            let key = string_key(&name);
+            Some(format!("{}{}", prefix, key[2..].strip_suffix('"').unwrap_or(&key[2..])))
+        }
+        _ => Some(longer.to_string()),
+    }
+}
+
+/// String key of a name, quoted the way Forge does when it has characters
+/// other than letters, digits, `.`, `-` and `_`.
+pub fn string_key(name: &str) -> String {
+    if !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || matches!(c, '.' | '-' | '_')) {
+        return format!("S:{}", name);
+    }
+    format!("S:\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Whether the line starts with a type prefix like `S:`.
+fn is_typed(line: &str) -> bool {
+    let mut chars = line.chars();
+    matches!((chars.next(), chars.next()), (Some(t), Some(':')) if t.is_ascii_alphabetic())
+}
+
+/// Length of the typed key at the start of `line`, the name may be quoted
+/// with `\"` and `\\` as escapes.
+fn key_length(line: &str) -> Result<usize, String> {
+    let name = &line[2..];
+    if let Some(quoted) = name.strip_prefix('"') {
+        let mut escaped = false;
+        for (i, c) in quoted.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => return Ok(2 + 1 + i + 1),
+                _ => {}
+            }
+        }
+        Err(format!("unterminated quoted key {}", line.trim_end()))
+    } else {
+        match name.find('=').or_else(|| name.find('<')) {
+            Some(0) | None => Err(format!("missing key name in '{}'", line.trim_end())),
+            Some(end) => Ok(2 + name[..end].trim_end().len()),
+        }
+    }
+}
+
+/// Removes the quotes around a category name, resolving escapes.
+fn unquote(name: &str) -> Result<String, String> {
+    let quoted = match name.strip_prefix('"').and_then(|x| x.strip_suffix('"')) {
+        Some(quoted) => quoted,
+        None => return Ok(name.to_string()),
+    };
+    let mut result = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some(escaped) => result.push(escaped),
+                None => return Err(format!("dangling escape in {}", name)),
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(text: &str) -> Vec<LangItem> {
+        parse(text).expect("valid lang file").0
+    }
+
+    fn item(key: &str, value: &str) -> LangItem {
+        (key.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn quoted_keys() {
+        let text = "languagefile {\n    S:\"Dirty Water.name\"=污水\n    S:\"a \\\"b\\\" c\"=d\n}\n";
+        assert_eq!(items(text), vec![item("S:\"Dirty Water.name\"", "污水"), item("S:\"a \\\"b\\\" c\"", "d")]);
+        assert_eq!(key_name("S:\"Dirty Water.name\""), "Dirty Water.name");
+        assert_eq!(key_name("S:\"a \\\"b\\\" c\""), "a \"b\" c");
+        assert_eq!(string_key("Dirty Water.name"), "S:\"Dirty Water.name\"");
+        assert_eq!(string_key("oredict.plateIron.name"), "S:oredict.plateIron.name");
+    }
+
+    #[test]
+    fn values_run_to_the_end_of_the_line() {
+        let text = "languagefile {\n    S:a=b=c\n    S:\"x=y\"= 1 + 1 = 2 \n    S:empty=\n}\n";
+        assert_eq!(items(text), vec![item("S:a", "b=c"), item("S:\"x=y\"", " 1 + 1 = 2 "), item("S:empty", "")]);
+    }
+
+    #[test]
+    fn only_the_lang_category_is_read() {
+        let text = "# Configuration file\nenablelangfile {\n    B:UseThisFileAsLanguageFile=true\n}\n\nlanguagefile {\n    S:a=b\n}\n";
+        assert_eq!(items(text), vec![item("S:a", "b")]);
+    }
+
+    #[test]
+    fn namespaces_cover_keys_of_every_format() {
+        assert!(in_namespace("S:oredict.ingotIron.name", "S:oredict."));
+        assert!(in_namespace("oredict.ingotIron.name", "S:oredict."));
+        assert!(in_namespace("oredict.ingotIron.name", "oredict."));
+        assert!(in_namespace("S:oredict.ingotIron.name", "oredict."));
+        assert!(in_namespace("Dirty Water.name", "S:\"Dirty W"));
+        assert!(in_namespace("S:\"Dirty Water.name\"", "Dirty W"));
+        assert!(in_namespace("anything", ""));
+        assert!(!in_namespace("B:oredict.x", "S:oredict."));
+        assert!(!in_namespace("gt.oredict.x", "S:oredict."));
+    }
+
+    #[test]
+    fn namespaces_intersect_by_name() {
+        let intersection = |a, b| namespace_intersection(a, b);
+        assert_eq!(intersection("oredict.plateDouble", "S:oredict."), Some("S:oredict.plateDouble".to_string()));
+        assert_eq!(intersection("S:oredict.", "oredict.plateDouble"), Some("S:oredict.plateDouble".to_string()));
+        assert_eq!(intersection("S:oredict.plate", "oredict."), Some("S:oredict.plate".to_string()));
+        assert_eq!(intersection("S:oredict.", "S:oredict."), Some("S:oredict.".to_string()));
+        assert_eq!(intersection("Dirty Water", "S:Dirty"), Some("S:\"Dirty Water".to_string()));
+        assert_eq!(intersection("S:\"Dirty W", "Dirty"), Some("S:\"Dirty W".to_string()));
+        assert_eq!(intersection("", "gt."), Some("gt.".to_string()));
+        assert_eq!(intersection("S:oredict.", "gt."), None);
+        assert_eq!(intersection("B:oredict.", "S:oredict."), None);
+        assert!(in_namespace("S:\"Dirty Water.name\"", &intersection("Dirty Water", "S:Dirty").unwrap()));
+    }
+
+    #[test]
+    fn bom_and_crlf() {
+        let text = "\u{feff}languagefile {\r\n    S:a=b\r\n    S:c=d\r\n}\r\n";
+        assert_eq!(items(text), vec![item("S:a", "b"), item("S:c", "d")]);
+        let layout = parse(text).unwrap().1;
+        assert!(layout.bom);
+        assert_eq!(layout.newline, "\r\n");
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        let error = |text: &str| parse(text).expect_err("invalid lang file");
+        assert_eq!(error("languagefile {\n    S:a=b\n    S:\"c=d\n}\n").line, 3);
+        assert_eq!(error("languagefile {\n    S:a b\n}\n").line, 2);
+        assert_eq!(error("languagefile {\n    S:a=b\n").line, 1);
+        assert_eq!(error("}\n").line, 1);
+        assert_eq!(error("languagefile {\n    S:list <\n        a\n}\n").line, 2);
+        assert_eq!(error("\u{feff}languagefile {\r\n\r\n    oops\r\n}\r\n").line, 3);
+    }
+
+    /// Writes `items` in the layout read from `text`.
+    fn rewrite(text: &str, items: &[LangItem]) -> String {
+        let mut output = vec![];
+        parse(text).unwrap().1.write(&mut output, items).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn round_trip_is_byte_identical() {
+        let texts = [
+            "# Configuration file\nenablelangfile {\n# a comment kept\n    B:UseThisFileAsLanguageFile=true\n}\n\nlanguagefile {\n    S:\"Dirty Water.name\"=污水\n\tS:a =  b=c \n    S:list <\n        x\n     >\n}\n",
+            "\u{feff}languagefile {\r\n    S:a=b\r\n\r\n    S:c=d\r\n}\r\n",
+            "languagefile {\n    S:a=b\n}",
+        ];
+        for text in texts {
+            assert_eq!(rewrite(text, &items(text)), text);
+        }
+    }
+
+    #[test]
+    fn edits_keep_the_layout() {
+        let text = "\u{feff}# header\r\nlanguagefile {\r\n  S:a = 1\r\n  S:b = 2\r\n}\r\n";
+        let edited = rewrite(text, &[item("S:b", "二"), item("S:c", "3")]);
+        // the space after `=` belongs to the old value
+        assert_eq!(edited, "\u{feff}# header\r\nlanguagefile {\r\n  S:b =二\r\n  S:c=3\r\n}\r\n");
+    }
+
+    #[test]
+    fn missing_lang_category_is_created() {
+        let text = "enablelangfile {\n    B:UseThisFileAsLanguageFile=true\n}\n";
+        assert_eq!(rewrite(text, &[item("S:a", "b")]), format!("{}languagefile {{\n    S:a=b\n}}\n", text));
+    }
+}
@@ -0,0 +1,246 @@
+//! Formats of lang files besides the Forge configuration of GregTech:
+//!
+//! * plain, as the `.lang` files of Minecraft before 1.13: `key=value` lines and `#` comments
+//! * JSON, as the `en_us.json` files of later versions: one object of string values
+//!
+//! Keys of these formats have no type prefix, `S:"Dirty Water.name"` of a Forge
+//! file is `Dirty Water.name` in them. Keys are converted when a file is
+//! written in a format other than the one it was read in.
+use std::borrow::Cow;
+use std::fmt::Formatter;
+use std::path::Path;
+use clap::ArgEnum;
+use serde::de::{MapAccess, Visitor};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::forge::{self, Layout, LayoutLine, ParseError};
+use crate::lang::LangItem;
+
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum LangFormat {
+    /// `languagefile { S:key=value }`, as written by GregTech
+    #[default]
+    Forge,
+    /// `key=value`
+    Plain,
+    /// `{"key": "value"}`
+    Json,
+}
+
+impl LangFormat {
+    /// The format implied by the extension of `path`, `None` for `.lang` which may be either Forge or plain.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "json" => Some(LangFormat::Json),
+            "cfg" => Some(LangFormat::Forge),
+            _ => None,
+        }
+    }
+
+    /// The format of a file read from `path`, looking at `text` when the extension is not enough.
+    pub fn detect(path: &Path, text: &str) -> Self {
+        if let Some(format) = Self::from_extension(path) {
+            return format;
+        }
+        // the first line that is neither blank nor a comment opens a category in Forge files
+        let first = text.trim_start_matches('\u{feff}').lines()
+            .map(|x| x.trim())
+            .find(|x| !x.is_empty() && !x.starts_with('#'));
+        match first {
+            Some(line) if !line.ends_with('{') => LangFormat::Plain,
+            _ => LangFormat::Forge,
+        }
+    }
+
+    /// The format of a file written to `path` from a source in `source` format.
+    pub fn for_target(path: &Path, source: LangFormat) -> Self {
+        match Self::from_extension(path) {
+            Some(format) => format,
+            // a .lang file can't hold JSON
+            None if source == LangFormat::Json => LangFormat::Plain,
+            None => source,
+        }
+    }
+
+    pub fn parse(self, text: &str) -> Result<(Vec<LangItem>, Option<Layout>), ParseError> {
+        match self {
+            LangFormat::Forge => forge::parse(text).map(|(items, layout)| (items, Some(layout))),
+            LangFormat::Plain => parse_plain(text).map(|(items, layout)| (items, Some(layout))),
+            LangFormat::Json => parse_json(text).map(|items| (items, None)),
+        }
+    }
+
+    /// Renders `items` in `layout`, or in the default layout of this format.
+    pub fn render(self, items: &[LangItem], layout: Option<&Layout>) -> Vec<u8> {
+        let mut bytes = vec![];
+        match self {
+            LangFormat::Forge => layout.cloned().unwrap_or_default().write(&mut bytes, items),
+            LangFormat::Plain => layout.cloned().unwrap_or_else(plain_layout).write(&mut bytes, items),
+            LangFormat::Json => {
+                serde_json::to_writer_pretty(&mut bytes, &JsonItems(Cow::Borrowed(items))).map_err(std::io::Error::from)
+                    .map(|_| bytes.push(b'\n'))
+            }
+        }.expect("writing to memory");
+        bytes
+    }
+
+    /// Converts `key` of this format to the form used by `to`.
+    pub fn convert_key(self, key: &str, to: LangFormat) -> String {
+        match (self == LangFormat::Forge, to == LangFormat::Forge) {
+            (true, false) => forge::key_name(key),
+            (false, true) => forge::string_key(key),
+            _ => key.to_string(),
+        }
+    }
+}
+
+/// An empty plain file.
+fn plain_layout() -> Layout {
+    Layout {
+        lang_category: false,
+        ..Layout::empty_like("")
+    }
+}
+
+/// Reads the entries of a plain file along with its layout. Values run
+/// verbatim from the first `=` to the end of the line.
+pub fn parse_plain(text: &str) -> Result<(Vec<LangItem>, Layout), ParseError> {
+    let mut items = vec![];
+    let mut layout = Layout {
+        lang_category: false,
+        ..Layout::empty_like(text)
+    };
+    for (i, raw) in text.strip_prefix('\u{feff}').unwrap_or(text).lines().enumerate() {
+        let trimmed = raw.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            layout.lines.push(LayoutLine::Raw(raw.to_string()));
+            continue;
+        }
+        match raw.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => {
+                items.push((key.to_string(), value.trim_end_matches('\r').to_string()));
+                layout.lines.push(LayoutLine::Entry {
+                    indent: String::new(),
+                    key: key.to_string(),
+                    separator: "=".to_string(),
+                });
+            }
+            _ => return Err(ParseError { line: i + 1, message: format!("expected key=value, found '{}'", trimmed) }),
+        }
+    }
+    Ok((items, layout))
+}
+
+/// Reads the entries of a JSON object in order, the values must be strings.
+pub fn parse_json(text: &str) -> Result<Vec<LangItem>, ParseError> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    serde_json::from_str::<JsonItems>(text)
+        .map(|x| x.0.into_owned())
+        .map_err(|e| {
+            // the line is reported by ParseError
+            let message = e.to_string();
+            let message = message.rsplit_once(" at line ").map_or(message.as_str(), |(x, _)| x);
+            ParseError { line: e.line(), message: format!("{} at column {}", message, e.column()) }
+        })
+}
+
+/// Entries of a JSON object, in order and with duplicates.
+struct JsonItems<'i>(Cow<'i, [LangItem]>);
+
+impl Serialize for JsonItems<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in self.0.iter() {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for JsonItems<'static> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ItemsVisitor;
+
+        impl<'de> Visitor<'de> for ItemsVisitor {
+            type Value = JsonItems<'static>;
+
+            fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+                formatter.write_str("an object of lang keys to strings")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut items = vec![];
+                while let Some(item) = map.next_entry::<String, String>()? {
+                    items.push(item);
+                }
+                Ok(JsonItems(Cow::Owned(items)))
+            }
+        }
+
+        deserializer.deserialize_map(ItemsVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(key: &str, value: &str) -> LangItem {
+        (key.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn plain_round_trip() {
+        let text = "# comment\n\nDirty Water.name=污水\noredict.a= =b \n";
+        let (items, layout) = LangFormat::Plain.parse(text).unwrap();
+        assert_eq!(items, vec![item("Dirty Water.name", "污水"), item("oredict.a", " =b ")]);
+        assert_eq!(LangFormat::Plain.render(&items, layout.as_ref()), text.as_bytes());
+        let edited = vec![item("Dirty Water.name", "脏水"), item("oredict.b", "乙")];
+        assert_eq!(LangFormat::Plain.render(&edited, layout.as_ref()), "# comment\n\nDirty Water.name=脏水\noredict.b=乙\n".as_bytes());
+        assert_eq!(LangFormat::Plain.render(&edited, None), "Dirty Water.name=脏水\noredict.b=乙\n".as_bytes());
+    }
+
+    #[test]
+    fn plain_errors_have_line_numbers() {
+        let error = parse_plain("a=b\n\nno separator\n").unwrap_err();
+        assert_eq!(error.line, 3);
+        assert_eq!(parse_plain("=b\n").unwrap_err().line, 1);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let items = vec![item("b.name", "乙 \"x\""), item("a.name", "甲\n"), item("b.name", "again")];
+        let text = LangFormat::Json.render(&items, None);
+        assert_eq!(std::str::from_utf8(&text).unwrap(), "{\n  \"b.name\": \"乙 \\\"x\\\"\",\n  \"a.name\": \"甲\\n\",\n  \"b.name\": \"again\"\n}\n");
+        let (parsed, layout) = LangFormat::Json.parse(std::str::from_utf8(&text).unwrap()).unwrap();
+        assert_eq!(parsed, items);
+        assert!(layout.is_none());
+        assert_eq!(parse_json("\u{feff}{}").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn json_errors_have_line_numbers() {
+        let error = parse_json("{\n  \"a\": \"b\",\n  \"c\": 1\n}").unwrap_err();
+        assert_eq!(error.line, 3);
+        assert!(error.message.contains("column"), "{}", error.message);
+    }
+
+    #[test]
+    fn formats_are_detected() {
+        assert_eq!(LangFormat::detect(Path::new("en_us.JSON"), "a=b"), LangFormat::Json);
+        assert_eq!(LangFormat::detect(Path::new("GregTech.cfg"), "a=b"), LangFormat::Forge);
+        assert_eq!(LangFormat::detect(Path::new("GregTech.lang"), "\u{feff}# c\n\nlanguagefile {\n}\n"), LangFormat::Forge);
+        assert_eq!(LangFormat::detect(Path::new("GregTech.lang"), "# c\na=b\n"), LangFormat::Plain);
+        assert_eq!(LangFormat::for_target(Path::new("zh_cn.lang"), LangFormat::Json), LangFormat::Plain);
+        assert_eq!(LangFormat::for_target(Path::new("zh_cn.json"), LangFormat::Forge), LangFormat::Json);
+        assert_eq!(LangFormat::for_target(Path::new("GregTech.lang"), LangFormat::Forge), LangFormat::Forge);
+    }
+
+    #[test]
+    fn keys_are_converted() {
+        assert_eq!(LangFormat::Forge.convert_key("S:\"Dirty Water.name\"", LangFormat::Json), "Dirty Water.name");
+        assert_eq!(LangFormat::Plain.convert_key("Dirty Water.name", LangFormat::Forge), "S:\"Dirty Water.name\"");
+        assert_eq!(LangFormat::Plain.convert_key("oredict.a", LangFormat::Forge), "S:oredict.a");
+        assert_eq!(LangFormat::Json.convert_key("a b", LangFormat::Plain), "a b");
+    }
+}
@@ -1,10 +1,9 @@
-use std::borrow::{BorrowMut, Cow};
-use std::hash::Hash;
-use std::rc::Rc;
-use crate::lang::{DictLangResult, LangItem, LangResult, RuleLangResult};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use crate::lang::{DictLangResult, LangResult, RuleLangResult};
 use crate::meta::GeneratorMeta;
 use crate::replacer::GroupRepository;
-use crate::rule::Rule;
+use crate::rule::{Rule, Template};
 
 pub enum GeneratorType<'a> {
     Dict(DictGenerator<'a>),
@@ -12,102 +11,179 @@ pub enum GeneratorType<'a> {
 }
 
 pub trait Generator<'a> {
-    fn meta(&self) -> &'a GeneratorMeta;
-    fn results(&self, repo: &Box<dyn GroupRepository<'a>>) -> Vec<Box<dyn LangResult<'a>>>;
+    fn meta(&self) -> &GeneratorMeta;
+    /// Position of the definition in `Config::generators`.
+    fn index(&self) -> usize;
+    /// Whether the results are kept in the on-disk cache.
+    fn cached(&self) -> bool {
+        false
+    }
+    /// Results of this generator whose source text is exactly `source`.
+    fn results(&self, repo: &dyn GroupRepository<'a>, source: &str) -> Vec<Box<dyn LangResult<'a>>>;
+}
+
+impl<'a> Generator<'a> for GeneratorType<'a> {
+    fn meta(&self) -> &GeneratorMeta {
+        match self {
+            GeneratorType::Dict(gen) => gen.meta(),
+            GeneratorType::Rule(gen) => gen.meta(),
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            GeneratorType::Dict(gen) => gen.index(),
+            GeneratorType::Rule(gen) => gen.index(),
+        }
+    }
+
+    fn cached(&self) -> bool {
+        match self {
+            GeneratorType::Dict(gen) => gen.cached(),
+            GeneratorType::Rule(gen) => gen.cached(),
+        }
+    }
+
+    fn results(&self, repo: &dyn GroupRepository<'a>, source: &str) -> Vec<Box<dyn LangResult<'a>>> {
+        match self {
+            GeneratorType::Dict(gen) => gen.results(repo, source),
+            GeneratorType::Rule(gen) => gen.results(repo, source),
+        }
+    }
 }
 
-#[derive(PartialEq, Eq, Hash)]
 pub struct DictGenerator<'a> {
-    pub meta: &'a GeneratorMeta,
-    pub dict: Vec<LangItem>
+    pub index: usize,
+    pub meta: Cow<'a, GeneratorMeta>,
+    pub dict: HashMap<String, String>
 }
 
 impl<'a> Generator<'a> for DictGenerator<'a> {
-    fn meta(&self) -> &'a GeneratorMeta {
-        self.meta
+    fn meta(&self) -> &GeneratorMeta {
+        &self.meta
     }
 
-    fn results(&self, repo: &Box<dyn GroupRepository<'a>>) -> Vec<Box<dyn LangResult<'a>>> {
-        let result = DictLangResult {
-            meta: self.meta,
-            result: self.dict.clone()
-        };
-        vec![Box::new(result)]
+    fn index(&self) -> usize {
+        self.index
+    }
+
+    fn results(&self, _repo: &dyn GroupRepository<'a>, source: &str) -> Vec<Box<dyn LangResult<'a>>> {
+        match self.dict.get(source) {
+            Some(target) => vec![Box::new(DictLangResult {
+                generator: self.index,
+                meta: self.meta.clone(),
+                result: vec![(source.to_string(), target.clone())]
+            })],
+            None => vec![]
+        }
     }
 }
 
 pub struct RuleGenerator<'a> {
+    pub index: usize,
     pub meta: &'a GeneratorMeta,
-    pub(crate) rules: &'a Vec<Rule>
+    pub(crate) rules: Vec<CompiledRule<'a>>
 }
 
-impl<'a, 'b: 'a> Generator<'a> for RuleGenerator<'a> {
-    fn meta(&self) -> &'a GeneratorMeta {
+pub(crate) struct CompiledRule<'a> {
+    pub rule: &'a Rule,
+    pub source: Template,
+    pub target: Template,
+}
+
+impl<'a> RuleGenerator<'a> {
+    pub fn new(index: usize, meta: &'a GeneratorMeta, rules: &'a [Rule]) -> Self {
+        let rules = rules.iter().map(|rule| CompiledRule {
+            rule,
+            source: rule.source_template(),
+            target: rule.target_template(),
+        }).collect();
+        RuleGenerator { index, meta, rules }
+    }
+}
+
+impl<'a> Generator<'a> for RuleGenerator<'a> {
+    fn meta(&self) -> &GeneratorMeta {
         self.meta
     }
 
-    fn results(&self, repo: &Box<dyn GroupRepository<'a>>) -> Vec<Box<dyn LangResult<'a>>> {
+    fn index(&self) -> usize {
+        self.index
+    }
+
+    fn cached(&self) -> bool {
+        self.meta.cached
+    }
+
+    fn results(&self, repo: &dyn GroupRepository<'a>, source: &str) -> Vec<Box<dyn LangResult<'a>>> {
         let mut results: Vec<Box<dyn LangResult<'a>>> = vec![];
-        for rule in self.rules.iter() {
+        for CompiledRule { rule, source: s_fmt, target: t_fmt } in self.rules.iter() {
             let subs = &rule.subs;
             let subs_size = subs.len();
             if subs_size == 0 {
                 continue;
             }
-            // init
-            let mut sub_results = vec![];
-            for sub in subs {
-                let mut sub_result = repo.get_group_results(sub).clone();
-                sub_result.retain(|x| !x.is_empty());
-                if sub_result.is_empty() {
-                    drop(sub_result);
-                    break;
+            for captures in s_fmt.captures(source) {
+                // every sub must be determined by the source text
+                if captures.len() != subs_size || captures.iter().any(|x| x.is_none()) {
+                    continue;
                 }
-                sub_results.push(sub_result);
-            }
-
-            if sub_results.is_empty() {
-                continue; // init failed, see clear() above
-            }
-
-            let mut begins = vec![];
-
-            loop {
-                // fill
-                for i in begins.len()..subs_size {
-                    begins.push(sub_results[i].clone());
+                // init
+                let mut sub_results = vec![];
+                for (sub, captured) in subs.iter().zip(captures.iter().flatten()) {
+                    let mut sub_result = repo.get_group_results(sub, captured);
+                    sub_result.retain(|x| !x.is_empty());
+                    if sub_result.is_empty() {
+                        // no member of the group matches, not valid
+                        sub_results.clear();
+                        break;
+                    }
+                    sub_results.push(sub_result);
                 }
 
-                let mut lang_combination = vec![];
-                for begin in begins.iter() {
-                    lang_combination.push(begin);
-                }
-                let mut generated_result = RuleLangResult {
-                    rule,
-                    meta: Cow::Borrowed(self.meta),
-                    subs: lang_combination.into_iter().flatten().map(|x| (*x).clone()).collect(),
-                    result: vec![]
-                };
-                if let Some(generated_meta) = generated_result.meta_combined() {
-                    generated_result.meta = Cow::Owned(generated_meta);
-                    results.push(Box::new(generated_result));
+                if sub_results.is_empty() {
+                    continue; // init failed, see clear() above
                 }
 
-                // step
-                for i in (0..(subs_size + 1)).rev() {
+                // only the members matching the source are combined here
+                let mut begins: Vec<usize> = vec![0; subs_size];
 
-                    if &begins[i - 1][0].result() == &sub_results[i - 1][0].result() {
-                        begins.pop();
-                    } else {
-                        break;
+                loop {
+                    let lang_combination: Vec<Box<dyn LangResult<'a>>> = begins.iter().enumerate()
+                        .map(|(i, begin)| sub_results[i][*begin].clone())
+                        .collect();
+                    let targets: Vec<String> = lang_combination.iter()
+                        .map(|x| x.result().first().map(|(_, target)| target.clone()).unwrap_or_default())
+                        .collect();
+                    let mut generated_result = RuleLangResult {
+                        generator: self.index,
+                        rule,
+                        meta: Cow::Borrowed(self.meta),
+                        subs: lang_combination,
+                        result: vec![(source.to_string(), t_fmt.format(&targets))]
+                    };
+                    if let Some(mut generated_meta) = generated_result.meta_combined() {
+                        generated_meta.priority = rule.priority.unwrap_or(generated_meta.priority);
+                        generated_result.meta = Cow::Owned(generated_meta);
+                        results.push(Box::new(generated_result));
                     }
-                }
 
-                if begins.is_empty() {
-                    break;
+                    // step
+                    while let Some(begin) = begins.pop() {
+                        if begin + 1 < sub_results[begins.len()].len() {
+                            begins.push(begin + 1);
+                            break;
+                        }
+                    }
+
+                    if begins.is_empty() {
+                        break;
+                    }
+                    // fill
+                    begins.resize(subs_size, 0);
                 }
             }
         }
         results
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,179 @@
+//! Dependencies between groups: a group with rules depends on the groups of their subs.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use crate::config::Config;
+use crate::meta::GeneratorMeta;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GroupGraph {
+    /// Every group, defined or referred to, with the groups it depends on
+    pub dependencies: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// Groups depending on themselves, each as a path `a -> b -> a` without the repeated end.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cycles(pub Vec<Vec<String>>);
+
+/// `a -> b -> a` for the cycle `[a, b]`.
+pub fn cycle_path(cycle: &[String]) -> String {
+    cycle.iter().chain(cycle.first()).map(|x| x.as_str()).collect::<Vec<&str>>().join(" -> ")
+}
+
+impl Display for Cycles {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let cycles: Vec<String> = self.0.iter().map(|x| cycle_path(x)).collect();
+        write!(f, "groups depending on themselves: {}", cycles.join("; "))
+    }
+}
+
+impl std::error::Error for Cycles {}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mark {
+    Visiting,
+    Done,
+}
+
+/// Groups reachable from `group` following `edges`, `group` itself excluded unless in a cycle.
+fn reachable(edges: &BTreeMap<String, BTreeSet<String>>, group: &str) -> BTreeSet<String> {
+    let mut found = BTreeSet::new();
+    let mut pending = vec![group.to_string()];
+    while let Some(next) = pending.pop() {
+        for x in edges.get(&next).into_iter().flatten() {
+            if found.insert(x.clone()) {
+                pending.push(x.clone());
+            }
+        }
+    }
+    found
+}
+
+impl GroupGraph {
+    pub fn new<'m, I>(metas: I) -> Self where I: IntoIterator<Item = &'m GeneratorMeta> {
+        let mut dependencies: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for meta in metas {
+            let subs: Vec<&String> = meta.rules.iter().flatten().flat_map(|x| x.subs.iter()).collect();
+            for sub in subs.iter() {
+                dependencies.entry(sub.to_string()).or_default();
+            }
+            dependencies.entry(meta.group.clone()).or_default().extend(subs.into_iter().cloned());
+        }
+        GroupGraph { dependencies }
+    }
+
+    /// Fails with every cycle found if a group depends on itself.
+    pub fn check_acyclic(&self) -> Result<(), Cycles> {
+        let mut cycles = vec![];
+        let mut marks: BTreeMap<&str, Mark> = BTreeMap::new();
+        for group in self.dependencies.keys() {
+            self.visit(group, &mut marks, &mut vec![], &mut cycles);
+        }
+        if cycles.is_empty() {
+            Ok(())
+        } else {
+            Err(Cycles(cycles))
+        }
+    }
+
+    /// Groups `group` depends on, directly or not.
+    pub fn descendants(&self, group: &str) -> BTreeSet<String> {
+        reachable(&self.dependencies, group)
+    }
+
+    /// Groups depending on `group`, directly or not.
+    pub fn ancestors(&self, group: &str) -> BTreeSet<String> {
+        let mut dependents: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for (x, dependencies) in self.dependencies.iter() {
+            for dependency in dependencies {
+                dependents.entry(dependency.clone()).or_default().insert(x.clone());
+            }
+        }
+        reachable(&dependents, group)
+    }
+
+    fn visit<'g>(&'g self, group: &'g str, marks: &mut BTreeMap<&'g str, Mark>, path: &mut Vec<&'g str>,
+                 cycles: &mut Vec<Vec<String>>) {
+        match marks.get(group) {
+            Some(Mark::Done) => return,
+            Some(Mark::Visiting) => {
+                let start = path.iter().position(|x| *x == group).unwrap_or_default();
+                cycles.push(path[start..].iter().map(|x| x.to_string()).collect());
+                return;
+            }
+            None => {}
+        }
+        marks.insert(group, Mark::Visiting);
+        path.push(group);
+        for dependency in self.dependencies.get(group).into_iter().flatten() {
+            self.visit(dependency, marks, path, cycles);
+        }
+        path.pop();
+        marks.insert(group, Mark::Done);
+    }
+}
+
+fn dot_quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+}
+
+/// Renders the groups of `config` as a Graphviz digraph, edges pointing from a group
+/// to the groups its rules refer to. Groups are labelled with their namespaces, those
+/// not completed are dashed and those never defined are red.
+///
+/// With `focus`, only the group, the groups depending on it and the groups it depends
+/// on are kept.
+pub fn to_dot(config: &Config, focus: Option<&str>) -> std::io::Result<String> {
+    let graph = GroupGraph::new(config.generators.iter());
+    let kept: Option<BTreeSet<String>> = match focus {
+        Some(group) if !graph.dependencies.contains_key(group) => {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("no group named '{}'", group)));
+        }
+        Some(group) => {
+            let mut kept = graph.ancestors(group);
+            kept.extend(graph.descendants(group));
+            kept.insert(group.to_string());
+            Some(kept)
+        }
+        None => None,
+    };
+    let is_kept = |group: &str| kept.as_ref().is_none_or(|x| x.contains(group));
+
+    // namespaces of every generator of a group, and whether any is completed
+    let mut groups: BTreeMap<&str, (BTreeSet<&str>, bool)> = BTreeMap::new();
+    for meta in config.generators.iter() {
+        let (namespaces, completed) = groups.entry(meta.group.as_str()).or_default();
+        namespaces.insert(meta.namespace.as_str());
+        *completed |= meta.completed;
+    }
+
+    let mut output = String::from("digraph groups {\n    node [shape=box];\n");
+    for group in graph.dependencies.keys().filter(|x| is_kept(x)) {
+        let mut attributes = vec![];
+        match groups.get(group.as_str()) {
+            Some((namespaces, completed)) => {
+                let namespaces: Vec<&str> = namespaces.iter().filter(|x| !x.is_empty()).copied().collect();
+                let mut label = group.clone();
+                if !namespaces.is_empty() {
+                    label.push('\n');
+                    label.push_str(&namespaces.join("\n"));
+                }
+                attributes.push(format!("label={}", dot_quote(&label)));
+                if !completed {
+                    attributes.push("style=dashed".to_string());
+                }
+            }
+            None => attributes.push("color=red".to_string()),
+        }
+        if focus == Some(group.as_str()) {
+            attributes.push("penwidth=2".to_string());
+        }
+        output.push_str(&format!("    {} [{}];\n", dot_quote(group), attributes.join(", ")));
+    }
+    for (group, dependencies) in graph.dependencies.iter().filter(|(x, _)| is_kept(x)) {
+        for dependency in dependencies.iter().filter(|x| is_kept(x)) {
+            output.push_str(&format!("    {} -> {};\n", dot_quote(group), dot_quote(dependency)));
+        }
+    }
+    output.push_str("}\n");
+    Ok(output)
+}
@@ -0,0 +1,208 @@
+//! State of the last incremental replace, kept in `.cache` of the workplace.
+//!
+//! A key is recomputed when its English or fallback entry changed, when a
+//! group that produced candidates for it changed, or when a changed group now
+//! produces candidates for it. A group changes with its enabled generators, their
+//! positions in the config which candidates refer to, and with every group it
+//! depends on. The outcome of every other key is taken
+//! from the state as it was.
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::cache::{self, cache_dir};
+use crate::config::{Config, RuntimeOptions};
+use crate::result::{Origin, Provenance, Translation};
+
+/// Bumped whenever the content of state files changes.
+const STATE_VERSION: &str = "1";
+
+/// `.cache/replace/<lang>.json` in the workplace.
+pub fn state_path(options: &RuntimeOptions) -> PathBuf {
+    cache_dir(Path::new(&options.workplace_path)).join("replace").join(format!("{}.json", options.lang))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StateEntry {
+    pub source: String,
+    pub fallback: Option<String>,
+    pub target: String,
+    pub origin: Origin,
+    pub provenance: Option<Provenance>,
+    pub rivals: Vec<Provenance>,
+    /// Groups producing candidates for the source
+    pub candidates: Vec<String>,
+}
+
+impl StateEntry {
+    pub fn to_translation(&self, key: &str) -> Translation {
+        Translation {
+            key: key.to_string(),
+            source: self.source.clone(),
+            target: self.target.clone(),
+            origin: self.origin,
+            provenance: self.provenance.clone(),
+            rivals: self.rivals.clone(),
+            fallback: self.fallback.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplaceState {
+    /// Everything but the config and the sources the outcome depends on
+    pub settings: String,
+    /// Digest of the enabled generators of every group
+    pub groups: BTreeMap<String, String>,
+    /// Outcome of every key of the main source, by the key
+    pub entries: HashMap<String, StateEntry>,
+}
+
+impl ReplaceState {
+    /// The state at `path`, `None` if there is none or it cannot be read.
+    pub fn load(path: &Path) -> Option<ReplaceState> {
+        fs::read(path).ok().and_then(|x| serde_json::from_slice(&x).ok())
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let contents = serde_json::to_vec(self).expect("strings are valid JSON");
+        crate::output::write_atomic(path, &contents)
+    }
+}
+
+/// Settings the outcome depends on apart from the config and the sources.
+pub fn settings(options: &RuntimeOptions) -> String {
+    let mut extensions: Vec<String> = options.enabled_extensions().into_iter().collect();
+    extensions.sort();
+    format!("{} {:?} {:?} {}", STATE_VERSION, options.main_source_path, options.extra_source_path, extensions.join(","))
+}
+
+/// Digest of the enabled generators of every group of `config`, with their positions.
+pub fn group_digests(config: &Config, options: &RuntimeOptions) -> BTreeMap<String, String> {
+    let enabled = options.enabled_extensions();
+    let mut groups: BTreeMap<&str, Vec<_>> = BTreeMap::new();
+    for (i, meta) in config.generators.iter().enumerate().filter(|(_, x)| x.is_enabled(&enabled)) {
+        groups.entry(meta.group.as_str()).or_default().push((i, meta));
+    }
+    groups.into_iter().map(|(group, metas)| (group.to_string(), cache::digest(metas))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use clap::Parser;
+    use crate::generator::GeneratorType;
+    use crate::lang::LangFile;
+    use crate::replacer::Replacer;
+    use super::*;
+
+    const CONFIG: &str = r#"
+generators:
+  - group: material
+    completed: false
+    dict: { Iron: 铁, Gold: 金 }
+  - group: plate
+    rules: [{ s: "{0} Plate", t: "{0}板", subs: [material] }]
+  - group: misc
+    dict: { Stick: 棍 }
+"#;
+
+    fn lang(items: &[(&str, &str)]) -> LangFile {
+        let mut file = LangFile::default();
+        file.items = items.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        file
+    }
+
+    fn english() -> LangFile {
+        lang(&[("S:a", "Iron Plate"), ("S:b", "Gold Plate"), ("S:c", "Stick"), ("S:d", "Copper Plate"), ("S:e", "Rock")])
+    }
+
+    fn fallback() -> LangFile {
+        lang(&[("S:d", "铜板"), ("S:e", "石头")])
+    }
+
+    /// Replaces incrementally from `previous`, checking that the outcome is that of a full run.
+    ///
+    /// The targets of `previous` are marked, so the keys whose outcome was recomputed are returned as well.
+    fn run(config: &str, args: &[&str], english: &LangFile, fallback: &LangFile, previous: Option<ReplaceState>)
+        -> (ReplaceState, BTreeSet<String>) {
+        let config: Config = serde_yaml::from_str(config).unwrap();
+        let options = RuntimeOptions::parse_from(["gt6tg", "--no-cache"].iter().chain(args));
+        let generators: Vec<GeneratorType> = config.generators();
+        let replacer = Replacer::new(&config, options, generators.iter().collect()).unwrap();
+        let previous = previous.map(|mut x| {
+            x.entries.values_mut().for_each(|entry| entry.target.insert_str(0, "reused "));
+            x
+        });
+        let (mut translations, mut state) = replacer.translate_incremental(english, Some(fallback), previous.as_ref());
+        let mut recomputed = BTreeSet::new();
+        for translation in translations.iter_mut() {
+            match translation.target.strip_prefix("reused ") {
+                Some(target) => translation.target = target.to_string(),
+                None => { recomputed.insert(translation.key.clone()); }
+            }
+        }
+        for entry in state.entries.values_mut() {
+            entry.target = entry.target.trim_start_matches("reused ").to_string();
+        }
+        assert_eq!(translations, replacer.translate(english, Some(fallback)));
+        (state, recomputed)
+    }
+
+    fn keys(keys: &[&str]) -> BTreeSet<String> {
+        keys.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn unchanged_inputs_reuse_every_key() {
+        let (state, recomputed) = run(CONFIG, &[], &english(), &fallback(), None);
+        assert_eq!(recomputed, keys(&["S:a", "S:b", "S:c", "S:d", "S:e"]));
+        let (_, recomputed) = run(CONFIG, &[], &english(), &fallback(), Some(state));
+        assert_eq!(recomputed, keys(&[]));
+    }
+
+    #[test]
+    fn dict_edits_recompute_the_keys_of_dependent_groups() {
+        let (state, _) = run(CONFIG, &[], &english(), &fallback(), None);
+        let edited = CONFIG.replace("Iron: 铁", "Iron: 鐵, Copper: 铜");
+        let (state, recomputed) = run(&edited, &[], &english(), &fallback(), Some(state));
+        // Copper Plate is generated now, Stick and Rock are not affected
+        assert_eq!(recomputed, keys(&["S:a", "S:b", "S:d"]));
+        assert_eq!(state.entries["S:a"].target, "鐵板");
+        assert_eq!(state.entries["S:d"].origin, Origin::Conflict);
+        let (_, recomputed) = run(CONFIG, &[], &english(), &fallback(), Some(state));
+        assert_eq!(recomputed, keys(&["S:a", "S:b", "S:d"]));
+    }
+
+    #[test]
+    fn fallback_edits_recompute_their_keys() {
+        let (state, _) = run(CONFIG, &[], &english(), &fallback(), None);
+        let edited = lang(&[("S:d", "铜板"), ("S:e", "岩石"), ("S:c", "木棍")]);
+        let (state, recomputed) = run(CONFIG, &[], &english(), &edited, Some(state));
+        assert_eq!(recomputed, keys(&["S:c", "S:e"]));
+        assert_eq!(state.entries["S:c"].origin, Origin::Conflict);
+        assert_eq!(state.entries["S:e"].target, "岩石");
+    }
+
+    #[test]
+    fn english_edits_recompute_their_keys() {
+        let (state, _) = run(CONFIG, &[], &english(), &fallback(), None);
+        let mut edited = english();
+        edited.items[2].1 = "Gold Plate".to_string();
+        edited.items.push(("S:f".to_string(), "Stick".to_string()));
+        let (state, recomputed) = run(CONFIG, &[], &edited, &fallback(), Some(state));
+        assert_eq!(recomputed, keys(&["S:c", "S:f"]));
+        assert_eq!(state.entries["S:c"].target, "金板");
+        assert_eq!(state.entries.len(), 6);
+    }
+
+    #[test]
+    fn other_settings_recompute_every_key() {
+        let (state, _) = run(CONFIG, &[], &english(), &fallback(), None);
+        let (_, recomputed) = run(CONFIG, &["-e", "qt"], &english(), &fallback(), Some(state));
+        assert_eq!(recomputed.len(), 5);
+    }
+}
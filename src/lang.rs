@@ -1,26 +1,39 @@
 use std::borrow::Cow;
 use std::fmt::Debug;
-use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::ops::Deref;
+use std::io::ErrorKind;
 use std::path::Path;
-use std::rc::Rc;
 use dyn_clone::DynClone;
-use strfmt::strfmt;
+use crate::forge;
+use crate::format::LangFormat;
 use crate::meta::GeneratorMeta;
+use crate::output::Output;
 use crate::rule::Rule;
 
 pub type LangItem = (String, String);
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct LangFile {
-    pub items: Vec<LangItem>
+    pub items: Vec<LangItem>,
+    /// Format of the file read, the keys of `items` are in its form
+    pub format: LangFormat,
+    /// Layout of the file read, `None` for the default layout of the format
+    layout: Option<forge::Layout>,
 }
 
 pub trait LangResult<'a>: 'a + DynClone + Debug {
     fn meta(&self) -> Cow<'a, GeneratorMeta>;
     fn result(&self) -> Cow<'_, Vec<LangItem>>;
     fn is_empty(&self) -> bool;
+    /// Position in `Config::generators` of the generator of this result.
+    fn generator(&self) -> usize;
+    /// Rule that generated this result, `None` for dict entries.
+    fn rule(&self) -> Option<&'a Rule> {
+        None
+    }
+    /// Results of the groups matched by the placeholders of the rule, in order.
+    fn subs(&self) -> &[Box<dyn LangResult<'a>>] {
+        &[]
+    }
 }
 
 impl<'a> PartialEq<Box<dyn LangResult<'a>>> for Box<dyn LangResult<'a>> {
@@ -33,13 +46,14 @@ dyn_clone::clone_trait_object!(LangResult<'_>);
 
 #[derive(PartialEq, Clone, Debug)]
 pub struct DictLangResult<'a> {
-    pub meta: &'a GeneratorMeta,
+    pub generator: usize,
+    pub meta: Cow<'a, GeneratorMeta>,
     pub result: Vec<LangItem>
 }
 
 impl<'a> LangResult<'a> for DictLangResult<'a> {
     fn meta(&self) -> Cow<'a, GeneratorMeta> {
-        Cow::Borrowed(self.meta)
+        self.meta.clone()
     }
 
     fn result(&self) -> Cow<'_, Vec<LangItem>> {
@@ -49,22 +63,27 @@ impl<'a> LangResult<'a> for DictLangResult<'a> {
     fn is_empty(&self) -> bool {
         self.result.is_empty()
     }
+
+    fn generator(&self) -> usize {
+        self.generator
+    }
 }
 
 #[derive(Clone, PartialEq, Debug)]
-pub struct RuleLangResult<'a: 'b, 'b> {
-    pub rule: &'b Rule,
+pub struct RuleLangResult<'a> {
+    pub generator: usize,
+    pub rule: &'a Rule,
     pub meta: Cow<'a, GeneratorMeta>,
     pub subs: Vec<Box<dyn LangResult<'a>>>,
     pub result: Vec<LangItem>
 }
 
-impl<'a: 'b, 'b> RuleLangResult<'a, 'b> {
+impl<'a> RuleLangResult<'a> {
     pub fn meta_combined(&self) -> Option<GeneratorMeta> {
         if self.is_empty() {
             return None;
         }
-        let mut meta_source = self.meta().clone().into_owned();
+        let mut meta_source = self.meta.header();
         for sub in self.subs.iter() {
             meta_source.combine(&sub.meta());
         }
@@ -74,137 +93,88 @@ impl<'a: 'b, 'b> RuleLangResult<'a, 'b> {
             Some(meta_source)
         }
     }
+}
 
-    pub fn result(&self) -> Vec<LangItem> {
-        self.generate()
+impl<'a> LangResult<'a> for RuleLangResult<'a> {
+    fn meta(&self) -> Cow<'a, GeneratorMeta> {
+        self.meta.clone()
     }
 
-    pub fn result_cached(&mut self, cached: bool) -> Vec<LangItem> {
-        if self.result.is_empty() {
-            self.result = self.generate();
-        }
-        self.result.clone()
+    fn result(&self) -> Cow<'_, Vec<LangItem>> {
+        Cow::Borrowed(&self.result)
     }
 
-    pub fn generate(&self) -> Vec<LangItem> {
-        let subs_sz = self.subs.len();
-
-        if self.is_empty() {
-            return Vec::default();
-        }
-        // init
-        let mut sub_results: Vec<LangItem> = vec![];
-        self.subs.iter().for_each(|x| x.result().into_iter().for_each(|y| sub_results.push(y)));
-        let mut begins = vec![];
-        let mut result = vec![];
-        let s_fmt = &self.rule.source;
-        let t_fmt = &self.rule.target;
-        let mut s_store = String::new();
-        let mut t_store = String::new();
-
-        loop {
-            // fill zeros
-            for i in begins.len()..subs_sz {
-                begins.push(&sub_results[i]);
-            }
-            // generate
-            s_store.clear();
-            t_store.clear();
-            for (source, target) in begins.iter() {
-                s_store.push_str(source);
-                t_store.push_str(target);
-            }
-            result.push((s_fmt.replace("{0}", &s_store), t_fmt.replace("{0}", &t_store)));
-            // step
-            for i in (0..(subs_sz + 1)).rev() {
-                unsafe {
-                    let mut ptr = (&*begins[i - 1]) as *const LangItem;
-                    ptr = ptr.add(1);
-                    begins[i - 1] = ptr.as_ref().unwrap();
-                }
-
-                if &begins[i - 1].0 == &sub_results[i - 1].0 {
-                    begins.pop();
-                } else {
-                    break;
-                }
-            }
-
-            if begins.len() <= 0 { break; }
-        }
-
-        return result;
+    fn generator(&self) -> usize {
+        self.generator
     }
-}
 
-impl<'a, 'b: 'a> LangResult<'a> for RuleLangResult<'a, 'b> {
-    fn meta(&self) -> Cow<'a, GeneratorMeta> {
-        self.meta.clone()
+    fn rule(&self) -> Option<&'a Rule> {
+        Some(self.rule)
     }
 
-    fn result(&self) -> Cow<'_, Vec<LangItem>> {
-        Cow::Owned(self.generate())
+    fn subs(&self) -> &[Box<dyn LangResult<'a>>] {
+        &self.subs
     }
 
     fn is_empty(&self) -> bool {
-        if self.subs.is_empty() || self.rule.subs.len() != self.subs.len() {
+        // misconfigure
+        if self.meta.is_empty() || self.subs.is_empty() || self.rule.subs.len() != self.subs.len() {
             return true;
         }
+        // empty input result
         if self.subs.iter().any(|x| x.is_empty()) {
             return true;
         }
-        if self.result.is_empty() {
-            return true;
-        }
-        return false;
+        // no matched output
+        self.result.is_empty()
     }
 }
 
 impl LangFile {
-    pub fn write<P>(&self, path: P) -> std::io::Result<()> where P: AsRef<Path> {
-        let file = File::options().write(true).create(true).open(path)?;
-        let mut writer = BufWriter::new(file);
-        writeln!(&mut writer, r#"# Configuration file
-enablelangfile {{
-    B:UseThisFileAsLanguageFile=true
-}}
-
-languagefile {{"#)?;
-        for (key, value) in self.items.iter() {
-            writeln!(&mut writer, "    {}={}", key, value)?;
+    /// An empty lang file to be written in the layout of this one.
+    pub fn empty_like(&self) -> LangFile {
+        LangFile {
+            items: vec![],
+            format: self.format,
+            layout: self.layout.clone(),
+        }
+    }
+
+    /// This file in `format`, converting the keys. The layout is only kept
+    /// when the format stays the same.
+    pub fn into_format(self, format: LangFormat) -> LangFile {
+        if self.format == format {
+            return self;
         }
-        writeln!(&mut writer, "}}\n")?;
+        let items = self.items.into_iter()
+            .map(|(key, value)| (self.format.convert_key(&key, format), value))
+            .collect();
+        LangFile { items, format, layout: None }
+    }
 
-        Ok(())
+    /// Renders the items, keeping the comments, categories and formatting of
+    /// the file this was read from.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.format.render(&self.items, self.layout.as_ref())
+    }
+
+    pub fn write<P>(&self, path: P) -> std::io::Result<()> where P: AsRef<Path> {
+        Output::default().write(path, &self.to_bytes())
     }
 
+    /// Reads a lang file, detecting its format from the extension and the content.
     pub fn read<P>(path: P) -> std::io::Result<LangFile> where P: AsRef<Path> {
-        let mut items: Vec<LangItem> = vec![];
-
-        let mut lang_started = false;
-
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
-        for x in reader.lines() {
-            let line = x?;
-            if lang_started {
-                if line.find('=').is_some() {
-                    let line = line.trim();
-                    let split: Vec<&str> = line.split("=").collect();
-                    items.push((split[0].to_string(), split[1].to_string()));
-                } else {
-                    if line.find("}").is_some() {
-                        lang_started = false;
-                    }
-                }
-
-            } else {
-                if line.find("languagefile").is_some() {
-                    lang_started = true;
-                }
-            }
-        }
+        Self::read_as(path, None)
+    }
 
-        Ok(LangFile { items })
+    /// Reads a lang file in `format`, or in the detected format if `None`.
+    pub fn read_as<P>(path: P, format: Option<LangFormat>) -> std::io::Result<LangFile> where P: AsRef<Path> {
+        let text = std::fs::read_to_string(&path)?;
+        let format = format.unwrap_or_else(|| LangFormat::detect(path.as_ref(), &text));
+        let (items, layout) = format.parse(&text).map_err(|e| {
+            std::io::Error::new(ErrorKind::InvalidData, format!("{}: {}", path.as_ref().display(), e))
+        })?;
+
+        Ok(LangFile { items, format, layout })
     }
-}
\ No newline at end of file
+}
@@ -1,19 +1,115 @@
+use std::path::Path;
 use clap::Parser;
-use crate::config::{Config, RuntimeOptions};
+use crate::check::Severity;
+use crate::config::{Command, Config, RuntimeOptions};
 use crate::lang::LangFile;
+use crate::replacer::Replacer;
+use crate::result::Translation;
 
 fn main() {
     let options: RuntimeOptions = RuntimeOptions::parse().determine_paths();
-    let config: Config = serde_yaml::from_str(&std::fs::read_to_string(options.config_path.unwrap()).expect("Cannot read config file")).expect("Cannot parse config");
-    println!("{:?}", config.generators);
-    let mut lang = LangFile::read("workplace/en/GregTech.lang").unwrap();
-    // println!("{:?}", lang);
+    if let Err(e) = run(options) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 }
 
+/// Prefixes an error with what failed, like `Cannot read config: config.yml: ...`.
+fn context(message: &'static str) -> impl Fn(std::io::Error) -> std::io::Error {
+    move |e| std::io::Error::new(e.kind(), format!("{}: {}", message, e))
+}
+
+/// Runs the command of `options`, or replaces when there is none.
+fn run(options: RuntimeOptions) -> std::io::Result<()> {
+    let config = Config::load(options.config_path.as_ref().unwrap()).map_err(context("Cannot read config"))?;
+    match options.command.clone() {
+        Some(Command::Extensions) => {
+            for extension in config.extensions() {
+                println!("{}", extension);
+            }
+        }
+        Some(Command::Check) => {
+            let diagnostics = check::check(&config);
+            for diagnostic in diagnostics.iter() {
+                println!("{}", diagnostic);
+            }
+            let errors = diagnostics.iter().filter(|x| x.severity == Severity::Error).count();
+            println!("{} errors, {} warnings", errors, diagnostics.len() - errors);
+            let status = check::exit_status(&diagnostics);
+            if status != 0 {
+                std::process::exit(status);
+            }
+        }
+        Some(Command::Graph { group, output }) => {
+            let dot = graph::to_dot(&config, group.as_deref()).map_err(context("Cannot export group graph"))?;
+            match output {
+                Some(path) => options.output().write(path, dot.as_bytes()).map_err(context("Cannot export group graph"))?,
+                None => print!("{}", dot),
+            }
+        }
+        Some(Command::Explain { key }) => {
+            print!("{}", explain::explain(&config, &options, &key).map_err(context("Cannot explain key"))?);
+        }
+        Some(Command::PoExport { fallback, output }) => {
+            let path = output.map_or_else(|| po::default_path(&options, fallback), |x| Path::new(&x).to_path_buf());
+            po::export(&options, fallback, &path).map_err(context("Cannot export PO file"))?;
+        }
+        Some(Command::PoImport { files }) => {
+            let report = po::import(&options, &files).map_err(context("Cannot import PO files"))?;
+            print!("{}", report);
+        }
+        Some(Command::XliffExport { version, output }) => {
+            let path = output.map_or_else(|| xliff::default_path(&options), |x| Path::new(&x).to_path_buf());
+            xliff::export(&options, version, &translations(&config, &options)?, &path).map_err(context("Cannot export XLIFF file"))?;
+        }
+        Some(Command::XliffImport { files }) => {
+            let report = xliff::import(&options, &files).map_err(context("Cannot import XLIFF files"))?;
+            print!("{}", report);
+        }
+        Some(Command::SheetExport { output }) => {
+            let path = output.map_or_else(|| sheet::default_path(&options), |x| Path::new(&x).to_path_buf());
+            sheet::export(&options, &translations(&config, &options)?, &path).map_err(context("Cannot export review sheet"))?;
+        }
+        Some(Command::SheetImport { files }) => {
+            let report = sheet::import(&options, &translations(&config, &options)?, &files).map_err(context("Cannot import review sheets"))?;
+            print!("{}", report);
+        }
+        None => {
+            let generators = config.generators();
+            let mut replacer = Replacer::new(&config, options, generators.iter().collect()).map_err(context("Invalid config"))?;
+            replacer.replace().map_err(context("Cannot replace lang files"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Outcome of replacing, without writing any lang file.
+fn translations(config: &Config, options: &RuntimeOptions) -> std::io::Result<Vec<Translation>> {
+    let generators = config.generators();
+    let replacer = Replacer::new(config, options.clone(), generators.iter().collect()).map_err(context("Invalid config"))?;
+    let (main_source, extra_source) = replacer.read_sources().map_err(context("Cannot read lang files"))?;
+    let translations = replacer.translate(&main_source, extra_source.as_ref());
+    replacer.save_cache().map_err(context("Cannot write cache"))?;
+    Ok(translations)
+}
+
+pub mod cache;
+pub mod check;
 pub mod config;
+pub mod exchange;
+pub mod explain;
+pub mod forge;
+pub mod format;
 pub mod generator;
+pub mod graph;
+pub mod incremental;
 pub mod meta;
 pub mod rule;
+pub mod sheet;
 pub mod replacer;
+pub mod report;
 pub mod lang;
-pub mod result;
\ No newline at end of file
+pub mod output;
+pub mod po;
+pub mod result;
+pub mod xliff;
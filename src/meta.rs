@@ -1,10 +1,9 @@
 use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
-use std::ops::AddAssign;
-use std::str::FromStr;
 use either::Either;
+use crate::forge;
 use crate::rule::Rule;
-use serde::{Serialize, Deserialize, Deserializer, de};
+use serde::{Serialize, Deserialize};
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
 pub struct GeneratorMeta {
@@ -13,9 +12,17 @@ pub struct GeneratorMeta {
     #[serde(default)]
     pub namespace: String,
 
-    #[serde(default)]
+    #[serde(default = "default_completed")]
     pub completed: bool,
 
+    /// Keep the results of the rules across runs, see `cache`
+    #[serde(default)]
+    pub cached: bool,
+
+    /// Breaks ties between equally specific candidates, the highest wins
+    #[serde(default)]
+    pub priority: i32,
+
     #[serde(default)]
     pub extensions: HashSet<String>,
 
@@ -33,6 +40,10 @@ pub struct StringOrHashMap {
     pub inner: Either<String, HashMap<String, String>>
 }
 
+fn default_completed() -> bool {
+    true
+}
+
 impl GeneratorMeta {
     pub fn with_namespace(&self, namespace: String) -> Self {
         let mut new = self.clone();
@@ -40,25 +51,56 @@ impl GeneratorMeta {
         new
     }
 
+    /// Copy of this meta without its dict and rules, for combined results.
+    pub fn header(&self) -> Self {
+        GeneratorMeta {
+            group: self.group.clone(),
+            namespace: self.namespace.clone(),
+            completed: self.completed,
+            cached: self.cached,
+            priority: self.priority,
+            extensions: self.extensions.clone(),
+            dict: None,
+            rules: None,
+        }
+    }
+
     pub fn is_empty(&self) -> bool {
         self.group.is_empty()
     }
 
+    /// Narrows this meta by `rhs`.
+    ///
+    /// The combined namespace is the intersection of the two by name, see
+    /// [`forge::namespace_intersection`]. When they are disjoint the group is
+    /// cleared to mark the result invalid. Extensions are merged, while the
+    /// priority stays that of this meta so that subs never raise it.
     pub fn combine(&mut self, rhs: &Self) {
-        let mut this_ns = &self.namespace;
-        let that_ns = &rhs.namespace;
-        if this_ns.rfind(that_ns) == Some(0) {
-            // this_ns is started with that_ns
-            // unchanged
-        } else if that_ns.rfind(this_ns) == Some(0) {
-            // that_ns is started with this_ns
-            this_ns = that_ns;
-        } else {
-            // not valid
-            self.group.clear();
-            return;
+        match forge::namespace_intersection(&self.namespace, &rhs.namespace) {
+            Some(namespace) => self.namespace = namespace,
+            None => {
+                // not valid
+                self.group.clear();
+                return;
+            }
         }
-        rhs.extensions.iter().all(|x| self.extensions.insert(x.clone()));
+        self.extensions.extend(rhs.extensions.iter().cloned());
+    }
+
+    /// Whether this meta is active with the `enabled` extensions.
+    ///
+    /// Every extension listed must be enabled, while one prefixed with `!`
+    /// must not be, e.g. `["qt", "!gtnh"]`.
+    pub fn is_enabled(&self, enabled: &HashSet<String>) -> bool {
+        self.extensions.iter().all(|x| match x.strip_prefix('!') {
+            Some(excluded) => !enabled.contains(excluded),
+            None => enabled.contains(x),
+        })
+    }
+
+    /// Position of `rule` among the rules of this meta, compared by address.
+    pub fn rule_index(&self, rule: &Rule) -> Option<usize> {
+        self.rules.iter().flatten().position(|x| std::ptr::eq(x, rule))
     }
 }
 
@@ -67,4 +109,50 @@ impl Hash for GeneratorMeta {
         self.group.hash(state);
         self.namespace.hash(state);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(group: &str, namespace: &str, priority: i32, extensions: &[&str]) -> GeneratorMeta {
+        GeneratorMeta {
+            group: group.to_string(),
+            namespace: namespace.to_string(),
+            priority,
+            extensions: extensions.iter().map(|x| x.to_string()).collect(),
+            ..GeneratorMeta::default()
+        }
+    }
+
+    #[test]
+    fn combine_keeps_the_priority_of_the_rule() {
+        let mut rule = meta("plate", "S:oredict.plate", 1, &["qt"]);
+        rule.combine(&meta("material", "S:oredict.", 10, &["!gtnh"]));
+        assert_eq!(rule, meta("plate", "S:oredict.plate", 1, &["qt", "!gtnh"]));
+    }
+
+    #[test]
+    fn combine_narrows_typed_and_untyped_namespaces() {
+        let mut rule = meta("plate", "oredict.plateDouble", 0, &[]);
+        rule.combine(&meta("material", "S:oredict.", 0, &[]));
+        assert_eq!(rule.namespace, "S:oredict.plateDouble");
+        let mut rule = meta("plate", "S:", 0, &[]);
+        rule.combine(&meta("material", "oredict.", 0, &[]));
+        assert_eq!(rule.namespace, "S:oredict.");
+        let mut rule = meta("plate", "oredict.", 0, &[]);
+        rule.combine(&meta("material", "", 0, &[]));
+        assert_eq!(rule.namespace, "oredict.");
+    }
+
+    #[test]
+    fn combine_clears_the_group_of_disjoint_namespaces() {
+        let mut rule = meta("plate", "S:oredict.plate", 0, &[]);
+        rule.combine(&meta("material", "gt.", 0, &["qt"]));
+        assert!(rule.is_empty());
+        assert!(rule.extensions.is_empty());
+        let mut rule = meta("plate", "B:oredict.", 0, &[]);
+        rule.combine(&meta("material", "S:oredict.", 0, &[]));
+        assert!(rule.is_empty());
+    }
+}
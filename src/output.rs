@@ -0,0 +1,165 @@
+//! Writing of output files: atomic replace, rotating backups and dry runs.
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default)]
+pub struct Output {
+    /// Only report what would change, nothing is written
+    pub dry_run: bool,
+    /// Number of backups kept of every overwritten file
+    pub backups: usize,
+}
+
+impl Output {
+    pub fn write<P>(&self, path: P, contents: &[u8]) -> std::io::Result<()> where P: AsRef<Path> {
+        let path = path.as_ref();
+        let old = match fs::read(path) {
+            Ok(old) => Some(old),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        if self.dry_run {
+            println!("{}", describe_change(path, old.as_deref(), contents));
+            return Ok(());
+        }
+        if old.as_deref() == Some(contents) {
+            return Ok(());
+        }
+        if old.is_some() && self.backups > 0 {
+            rotate_backups(path, self.backups)?;
+        }
+        write_atomic(path, contents)
+    }
+}
+
+/// Writes to a temporary file next to `path` and renames it over `path`,
+/// so that `path` never holds a partially written file.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(path.file_name().unwrap_or_default());
+    tmp_name.push(".tmp");
+    let tmp = path.with_file_name(tmp_name);
+    let result = File::create(&tmp)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// `GregTech.lang` -> `GregTech.lang.bak.1`, 1 being the newest.
+pub fn backup_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".bak.{}", index));
+    path.with_file_name(name)
+}
+
+fn rotate_backups(path: &Path, count: usize) -> std::io::Result<()> {
+    let oldest = backup_path(path, count);
+    if oldest.exists() {
+        fs::remove_file(oldest)?;
+    }
+    for i in (1..count).rev() {
+        let backup = backup_path(path, i);
+        if backup.exists() {
+            fs::rename(backup, backup_path(path, i + 1))?;
+        }
+    }
+    fs::copy(path, backup_path(path, 1))?;
+    Ok(())
+}
+
+fn describe_change(path: &Path, old: Option<&[u8]>, new: &[u8]) -> String {
+    let new_text = String::from_utf8_lossy(new);
+    match old {
+        None => format!("would create {} ({} lines)", path.display(), new_text.lines().count()),
+        Some(old) if old == new => format!("unchanged {}", path.display()),
+        Some(old) => {
+            // lines only on one side, regardless of order
+            let old_text = String::from_utf8_lossy(old);
+            let mut counts: HashMap<&str, isize> = HashMap::new();
+            old_text.lines().for_each(|x| *counts.entry(x).or_default() -= 1);
+            new_text.lines().for_each(|x| *counts.entry(x).or_default() += 1);
+            let added: isize = counts.values().filter(|x| **x > 0).sum();
+            let removed: isize = -counts.values().filter(|x| **x < 0).sum::<isize>();
+            format!("would update {} (+{} -{} lines)", path.display(), added, removed)
+        }
+    }
+}
+
+/// An empty folder named after `name` in the temporary folder, for tests working on files.
+#[cfg(test)]
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gt6tg-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("temporary folder is writable");
+    dir
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir).unwrap()
+            .map(|x| x.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn atomic_write_leaves_no_temporary_file() {
+        let dir = temp_dir("atomic");
+        let path = dir.join("GregTech.lang");
+        write_atomic(&path, b"a").unwrap();
+        write_atomic(&path, b"b").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"b");
+        assert_eq!(files(&dir), vec!["GregTech.lang"]);
+        assert!(write_atomic(&dir.join("missing").join("GregTech.lang"), b"c").is_err());
+        assert_eq!(files(&dir), vec!["GregTech.lang"]);
+    }
+
+    #[test]
+    fn backups_rotate() {
+        let dir = temp_dir("backups");
+        let path = dir.join("GregTech.lang");
+        let output = Output { dry_run: false, backups: 2 };
+        for contents in ["1", "2", "3", "3", "4"] {
+            output.write(&path, contents.as_bytes()).unwrap();
+        }
+        assert_eq!(files(&dir), vec!["GregTech.lang", "GregTech.lang.bak.1", "GregTech.lang.bak.2"]);
+        assert_eq!(fs::read(&path).unwrap(), b"4");
+        // unchanged contents are not written again, nor backed up
+        assert_eq!(fs::read(backup_path(&path, 1)).unwrap(), b"3");
+        assert_eq!(fs::read(backup_path(&path, 2)).unwrap(), b"2");
+    }
+
+    #[test]
+    fn dry_run_leaves_files_untouched() {
+        let dir = temp_dir("dry-run");
+        let path = dir.join("GregTech.lang");
+        let output = Output { dry_run: true, backups: 1 };
+        output.write(&path, b"a").unwrap();
+        assert!(files(&dir).is_empty());
+        fs::write(&path, b"a\nb\n").unwrap();
+        output.write(&path, b"a\nc\nd\n").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"a\nb\n");
+        assert_eq!(files(&dir), vec!["GregTech.lang"]);
+    }
+
+    #[test]
+    fn changes_are_described() {
+        let path = Path::new("GregTech.lang");
+        assert_eq!(describe_change(path, None, b"a\nb\n"), "would create GregTech.lang (2 lines)");
+        assert_eq!(describe_change(path, Some(b"a\n"), b"a\n"), "unchanged GregTech.lang");
+        assert_eq!(describe_change(path, Some(b"a\nb\n"), b"b\nc\nd\n"), "would update GregTech.lang (+2 -1 lines)");
+    }
+}
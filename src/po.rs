@@ -0,0 +1,330 @@
+//! Gettext PO files for translators working in PO editors:
+//!
+//! ```plain
+//! msgctxt "S:\"Dirty Water.name\""
+//! msgid "Dirty Water"
+//! msgstr "污水"
+//! ```
+//!
+//! The lang key is the context and the English source the id, keys are in the
+//! form of the main source. Exporting writes the unknown entries, optionally
+//! along with the fallback, and importing merges the translated entries back
+//! into the fallback.
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use crate::config::RuntimeOptions;
+use crate::exchange::{self, invalid_data, ImportEntry, ImportReport};
+use crate::forge::ParseError;
+use crate::lang::LangFile;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PoEntry {
+    pub context: Option<String>,
+    pub id: String,
+    pub str: String,
+    /// `#. ` comments
+    pub extracted_comments: Vec<String>,
+    pub fuzzy: bool,
+    /// Commented out with `#~`
+    pub obsolete: bool,
+    /// 1-based line the entry starts at
+    pub line: usize,
+}
+
+impl PoEntry {
+    pub fn is_header(&self) -> bool {
+        self.context.is_none() && self.id.is_empty()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Context,
+    Id,
+    Str,
+    /// `msgid_plural` and `msgstr[n]` for n > 0, lang entries have no plurals
+    Ignored,
+}
+
+/// Reads the entries of a PO file, the header included.
+pub fn parse(text: &str) -> Result<Vec<PoEntry>, ParseError> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut entries = vec![];
+    let mut entry: Option<PoEntry> = None;
+    let mut has_str = false;
+    let mut field: Option<Field> = None;
+    for (i, raw) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let error = |message: String| ParseError { line: line_no, message };
+        let mut line = raw.trim();
+        let obsolete = line.starts_with("#~");
+        if obsolete {
+            line = line[2..].trim_start();
+            if line.starts_with('|') {
+                // previous msgid of an obsolete entry
+                continue;
+            }
+        }
+
+        if line.is_empty() {
+            if !obsolete {
+                entries.extend(entry.take());
+                has_str = false;
+                field = None;
+            }
+            continue;
+        }
+        // a new entry may follow the last one without a blank line
+        if has_str && (line.starts_with('#') || line.starts_with("msgctxt") || line.starts_with("msgid ")) {
+            entries.extend(entry.take());
+            has_str = false;
+            field = None;
+        }
+        let current = entry.get_or_insert_with(|| PoEntry { line: line_no, ..PoEntry::default() });
+        current.obsolete |= obsolete;
+
+        if let Some(flags) = line.strip_prefix("#,") {
+            current.fuzzy |= flags.split(',').any(|x| x.trim() == "fuzzy");
+            continue;
+        }
+        if let Some(comment) = line.strip_prefix("#.") {
+            current.extracted_comments.push(comment.trim().to_string());
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+
+        let (keyword, rest) = match line.split_once(char::is_whitespace) {
+            Some((keyword, rest)) if !line.starts_with('"') => (keyword, rest.trim()),
+            _ => ("", line),
+        };
+        let value = unquote(rest).map_err(error)?;
+        let target = match keyword {
+            "" => field.ok_or_else(|| error("string outside of an entry".to_string()))?,
+            "msgctxt" => Field::Context,
+            "msgid" => Field::Id,
+            "msgid_plural" => Field::Ignored,
+            "msgstr" | "msgstr[0]" => Field::Str,
+            _ if keyword.starts_with("msgstr[") => Field::Ignored,
+            _ => return Err(error(format!("unknown keyword '{}'", keyword))),
+        };
+        match target {
+            Field::Context => current.context.get_or_insert_with(String::new).push_str(&value),
+            Field::Id => current.id.push_str(&value),
+            Field::Str => {
+                has_str = true;
+                current.str.push_str(&value)
+            }
+            Field::Ignored => {}
+        }
+        field = Some(target);
+    }
+    entries.extend(entry);
+    Ok(entries)
+}
+
+/// Renders `entries` after a header with the given fields.
+pub fn render(header: &[(&str, &str)], entries: &[PoEntry]) -> Vec<u8> {
+    let mut output = String::new();
+    output.push_str("msgid \"\"\nmsgstr \"\"\n");
+    for (name, value) in header {
+        output.push_str(&format!("{}\n", quote(&format!("{}: {}\n", name, value))));
+    }
+    for entry in entries.iter() {
+        output.push('\n');
+        let prefix = if entry.obsolete { "#~ " } else { "" };
+        for comment in entry.extracted_comments.iter() {
+            output.push_str(&format!("#. {}\n", comment));
+        }
+        if entry.fuzzy {
+            output.push_str("#, fuzzy\n");
+        }
+        if let Some(context) = &entry.context {
+            output.push_str(&format!("{}msgctxt {}\n", prefix, quote(context)));
+        }
+        output.push_str(&format!("{}msgid {}\n", prefix, quote(&entry.id)));
+        output.push_str(&format!("{}msgstr {}\n", prefix, quote(&entry.str)));
+    }
+    output.into_bytes()
+}
+
+fn quote(text: &str) -> String {
+    let mut result = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            _ => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+fn unquote(text: &str) -> Result<String, String> {
+    let inner = text.strip_prefix('"').and_then(|x| x.strip_suffix('"'))
+        .filter(|_| text.len() >= 2)
+        .ok_or_else(|| format!("expected a quoted string, found '{}'", text))?;
+    let mut result = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('t') => result.push('\t'),
+            Some(c @ ('"' | '\\')) => result.push(c),
+            Some(c) => return Err(format!("unknown escape '\\{}'", c)),
+            None => return Err(format!("dangling escape in {}", text)),
+        }
+    }
+    Ok(result)
+}
+
+/// `GregTech.po` with the fallback, `GregTech.pot` without, in the folder of the language.
+pub fn default_path(options: &RuntimeOptions, fallback: bool) -> PathBuf {
+    let name = if fallback { "GregTech.po" } else { "GregTech.pot" };
+    Path::new(&options.workplace_path).join(&options.lang).join(name)
+}
+
+/// Exports the unknown entries, and the fallback ones if `fallback`, to `path`.
+pub fn export(options: &RuntimeOptions, fallback: bool, path: &Path) -> std::io::Result<()> {
+    let main_source = LangFile::read_as(options.main_source_path.as_ref().unwrap(), options.source_format)?;
+    let main_format = main_source.format;
+    let english: HashMap<&str, &str> = main_source.items.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+
+    let mut entries = vec![];
+    let mut exported = HashSet::new();
+    let unknown = LangFile::read(options.extra_target_path.as_ref().expect("No unknown file path"))?.into_format(main_format);
+    for (key, value) in unknown.items.into_iter() {
+        let id = english.get(key.as_str()).map_or(value, |x| x.to_string());
+        exported.insert(key.clone());
+        entries.push(PoEntry { context: Some(key), id, ..PoEntry::default() });
+    }
+    if fallback {
+        if let Some(fallback_path) = options.extra_source_path.as_ref().filter(|x| Path::new(x).exists()) {
+            let fallback = LangFile::read_as(fallback_path, options.source_format)?.into_format(main_format);
+            for (key, value) in fallback.items.into_iter() {
+                // entries no longer in the English source are left out
+                if let Some(id) = english.get(key.as_str()).filter(|_| !exported.contains(&key)) {
+                    entries.push(PoEntry {
+                        context: Some(key),
+                        id: id.to_string(),
+                        str: value,
+                        extracted_comments: vec!["fallback".to_string()],
+                        ..PoEntry::default()
+                    });
+                }
+            }
+        }
+    }
+
+    let mut header = vec![
+        ("Project-Id-Version", "GregTech 6"),
+        ("MIME-Version", "1.0"),
+        ("Content-Type", "text/plain; charset=UTF-8"),
+        ("Content-Transfer-Encoding", "8bit"),
+    ];
+    if fallback {
+        header.push(("Language", &options.lang));
+    }
+    options.output().write(path, &render(&header, &entries))
+}
+
+/// Merges the translated entries of `files` into the fallback.
+pub fn import<P: AsRef<Path>>(options: &RuntimeOptions, files: &[P]) -> std::io::Result<ImportReport> {
+    let mut imported = vec![];
+    for file in files {
+        let file = file.as_ref();
+        let entries = parse(&std::fs::read_to_string(file)?).map_err(|e| invalid_data(file, e))?;
+        imported.extend(entries.into_iter().filter(|x| !x.is_header()).map(|entry| ImportEntry {
+            position: format!("{}:{}", file.display(), entry.line),
+            key: entry.context,
+            source: entry.id,
+            target: entry.str,
+            fuzzy: entry.fuzzy,
+            obsolete: entry.obsolete,
+            generated: false,
+        }));
+    }
+    exchange::import(options, imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(context: &str, id: &str, str: &str) -> (Option<String>, String, String) {
+        (Some(context.to_string()), id.to_string(), str.to_string())
+    }
+
+    fn fields(entries: &[PoEntry]) -> Vec<(Option<String>, String, String)> {
+        entries.iter().filter(|x| !x.is_header()).map(|x| (x.context.clone(), x.id.clone(), x.str.clone())).collect()
+    }
+
+    #[test]
+    fn multi_line_strings() {
+        let text = "msgid \"\"\nmsgstr \"\"\n\"Language: zh\\n\"\n\nmsgctxt \"S:a\"\nmsgid \"\"\n\"Dirty \"\n\"Water\"\nmsgstr \"\"\n\"污\"\n\"水\\n\\\"x\\\"\"\n";
+        let entries = parse(text).unwrap();
+        assert!(entries[0].is_header());
+        assert_eq!(entries[0].str, "Language: zh\n");
+        assert_eq!(fields(&entries), vec![entry("S:a", "Dirty Water", "污水\n\"x\"")]);
+        assert_eq!(entries[1].line, 5);
+    }
+
+    #[test]
+    fn obsolete_entries() {
+        let text = "msgctxt \"S:a\"\nmsgid \"A\"\nmsgstr \"甲\"\n\n#~ msgctxt \"S:b\"\n#~ msgid \"B\"\n#~ msgstr \"\"\n#~ \"乙\"\n";
+        let entries = parse(text).unwrap();
+        assert_eq!(fields(&entries), vec![entry("S:a", "A", "甲"), entry("S:b", "B", "乙")]);
+        assert!(!entries[0].obsolete);
+        assert!(entries[1].obsolete);
+    }
+
+    #[test]
+    fn fuzzy_flag() {
+        let text = "#. S:a\n#, c-format, fuzzy\nmsgctxt \"S:a\"\nmsgid \"A\"\nmsgstr \"甲\"\n\nmsgctxt \"S:b\"\nmsgid \"B\"\nmsgstr \"乙\"\n";
+        let entries = parse(text).unwrap();
+        assert!(entries[0].fuzzy);
+        assert_eq!(entries[0].extracted_comments, vec!["S:a".to_string()]);
+        assert!(!entries[1].fuzzy);
+    }
+
+    #[test]
+    fn entries_without_blank_lines() {
+        let text = "msgctxt \"S:a\"\nmsgid \"A\"\nmsgstr \"甲\"\n#, fuzzy\nmsgctxt \"S:b\"\nmsgid \"B\"\nmsgstr \"乙\"\nmsgid \"C\"\nmsgstr \"丙\"\n";
+        let entries = parse(text).unwrap();
+        assert_eq!(fields(&entries), vec![
+            entry("S:a", "A", "甲"),
+            entry("S:b", "B", "乙"),
+            (None, "C".to_string(), "丙".to_string()),
+        ]);
+        assert!(!entries[0].fuzzy);
+        assert!(entries[1].fuzzy);
+        assert_eq!(entries[2].line, 8);
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        assert_eq!(parse("msgid \"A\"\nmsgstr \"\\q\"\n").unwrap_err().line, 2);
+        assert_eq!(parse("\"orphan\"\n").unwrap_err().line, 1);
+        assert_eq!(parse("msgid \"A\"\nmsgfoo \"B\"\n").unwrap_err().line, 2);
+    }
+
+    #[test]
+    fn render_round_trip() {
+        let entries = vec![
+            PoEntry { context: Some("S:a".to_string()), id: "A \"x\"".to_string(), str: "甲\n".to_string(), fuzzy: true, line: 4, ..PoEntry::default() },
+            PoEntry { context: Some("S:b".to_string()), id: "B".to_string(), obsolete: true, line: 9, ..PoEntry::default() },
+        ];
+        let text = String::from_utf8(render(&[], &entries)).unwrap();
+        assert_eq!(parse(&text).unwrap()[1..], entries[..]);
+    }
+}
@@ -1,193 +1,580 @@
-use std::any::Any;
-use std::borrow::{Cow};
-use std::cell::{Ref, RefCell};
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
-use std::hash::Hash;
-use std::marker::PhantomData;
+use std::path::Path;
 use crate::{Config, LangFile, RuntimeOptions};
+use crate::cache::{self, CachedResult, GeneratorCache};
+use crate::forge;
+use crate::format::LangFormat;
+use crate::incremental::{self, ReplaceState, StateEntry};
 use crate::generator::Generator;
+use crate::graph::GroupGraph;
 use crate::lang::LangResult;
-use crate::meta::GeneratorMeta;
+use crate::report::{AmbiguityReport, ConflictReport, ProvenanceSidecar};
+use crate::result::{Decision, Origin, Provenance, Reason, SubProvenance, Translation};
 
 pub trait GroupRepository<'a> {
-    fn get_group_results(&self, group: &str) -> Ref<Vec<Box<dyn LangResult<'a>>>>;
+    /// Results of every generator in `group` whose source text is exactly `source`.
+    fn get_group_results(&self, group: &str, source: &str) -> Vec<Box<dyn LangResult<'a>>>;
 }
 
-#[derive(Debug)]
-pub struct Replacer<'a, G> where G: Generator<'a> + Eq + Hash {
-    pub config: Config,
+/// Matched results keyed by group and source text.
+type GroupCache<'a> = RefCell<HashMap<(String, String), Vec<Box<dyn LangResult<'a>>>>>;
+
+pub struct Replacer<'a, G> where G: Generator<'a> {
+    pub config: &'a Config,
     pub options: RuntimeOptions,
     generators: Vec<&'a G>,
-    result_cache: RefCell<HashMap<&'a G, Vec<Box<dyn LangResult<'a>>>>>,
-    group_cache: RefCell<HashMap<String, Vec<Box<dyn LangResult<'a>>>>>,
+    groups: HashMap<String, Vec<usize>>,
+    graph: GroupGraph,
+    group_cache: GroupCache<'a>,
+    /// On-disk caches of cached generators by index
+    caches: HashMap<usize, RefCell<GeneratorCache>>,
 }
 
-impl<'a, G> Replacer<'a, G> where G: Generator<'a> + Eq + Hash {
-    pub fn get_generator_results(self: &Box<Self>, gen: &'a G) -> Option<Vec<Box<dyn LangResult<'a>>>> {
-        if let Some(rcache_found) = self.result_cache.borrow().get(gen) {
-            if self.result_cache.borrow().iter().last().map(|x| x.1 == rcache_found).unwrap_or(false) {
-                let new_results = gen.results(self as &Box<dyn GroupRepository<'a>>);
-                self.result_cache.borrow_mut().insert(gen, new_results.clone());
-                return Some(new_results);
-            } else {
-                return Some(rcache_found.clone());
+impl<'a, G> Replacer<'a, G> where G: Generator<'a> {
+    /// Fails if a group refers to itself through the subs of its rules, which would never end.
+    pub fn new(config: &'a Config, options: RuntimeOptions, generators: Vec<&'a G>) -> std::io::Result<Self> {
+        // generators of disabled extensions take no part in generate nor in groups
+        let enabled = options.enabled_extensions();
+        let generators: Vec<&'a G> = generators.into_iter().filter(|x| x.meta().is_enabled(&enabled)).collect();
+        let mut groups: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, gen) in generators.iter().enumerate() {
+            groups.entry(gen.meta().group.clone()).or_default().push(i);
+        }
+        let graph = GroupGraph::new(generators.iter().map(|x| x.meta()));
+        graph.check_acyclic()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?;
+        let mut caches = HashMap::new();
+        if !options.no_cache {
+            let dir = cache::generator_cache_dir(&options);
+            for (i, gen) in generators.iter().enumerate().filter(|(_, x)| x.cached()) {
+                let dependencies = graph.descendants(&gen.meta().group);
+                let key = cache::cache_key((gen.index(), gen.meta()), config.generators.iter().enumerate()
+                    .filter(|(_, x)| x.is_enabled(&enabled) && dependencies.contains(&x.group)));
+                caches.insert(i, RefCell::new(GeneratorCache::load(&dir, key)));
             }
         }
-        None
+        Ok(Replacer {
+            config,
+            options,
+            generators,
+            groups,
+            graph,
+            group_cache: RefCell::new(HashMap::new()),
+            caches,
+        })
     }
 
-    pub fn replace(self: &mut Box<Self>) -> std::io::Result<()> {
-        let main_source = LangFile::read(self.options.main_source_path.as_ref().unwrap())?;
-
-        let mut extra_dict: HashMap<String, String> = HashMap::new();
+    pub fn get_generator_results(&self, index: usize, source: &str) -> Vec<Box<dyn LangResult<'a>>> {
+        let generator = self.generators[index];
+        let cache = match self.caches.get(&index) {
+            Some(cache) => cache,
+            None => return generator.results(self, source),
+        };
+        if let Some(cached) = cache.borrow().get(source) {
+            return cached.iter().map(|x| x.to_result(self.config, generator.index(), source)).collect();
+        }
+        let results = generator.results(self, source);
+        let mut cached = vec![];
+        for result in results.iter() {
+            let meta = result.meta();
+            let rule = result.rule().and_then(|x| generator.meta().rule_index(x));
+            let subs = self.sub_provenance(result.as_ref());
+            cached.extend(result.result().iter().map(|(_, target)| CachedResult::new(&meta, rule, subs.clone(), target)));
+        }
+        cache.borrow_mut().insert(source.to_string(), cached);
+        results
+    }
 
-        let mut valid_extra_source = false;
-        if let Some(extra_lang) = self.options.extra_source_path.as_ref().and_then(|x| LangFile::read(x).ok()) {
-            extra_lang.items.into_iter().for_each(|(k, v)| { extra_dict.insert(k, v); });
-            valid_extra_source = true;
+    /// Writes the caches of cached generators to the workplace and removes outdated ones,
+    /// nothing is written in a dry run.
+    pub fn save_cache(&self) -> std::io::Result<()> {
+        if self.options.dry_run || self.options.no_cache {
+            return Ok(());
+        }
+        let dir = cache::generator_cache_dir(&self.options);
+        for cache in self.caches.values() {
+            cache.borrow().save(&dir)?;
         }
+        let keys: Vec<String> = self.caches.values().map(|x| x.borrow().key.clone()).collect();
+        cache::prune(&dir, &keys.iter().map(|x| x.as_str()).collect())
+    }
 
-        let extra_target_path = self.options.extra_target_path.as_ref();
+    /// Reads the main source and the fallback, `None` if there is no fallback file.
+    pub fn read_sources(&self) -> std::io::Result<(LangFile, Option<LangFile>)> {
+        let main_source = LangFile::read_as(self.options.main_source_path.as_ref().unwrap(), self.options.source_format)?;
+        let extra_source = match self.options.extra_source_path.as_ref().filter(|x| Path::new(x).exists()) {
+            Some(extra_source_path) => Some(LangFile::read_as(extra_source_path, self.options.source_format)?),
+            None => None,
+        };
+        Ok((main_source, extra_source))
+    }
 
-        let mut main_target = LangFile::default();
-        let mut extra_target = LangFile::default();
-        let mut extra_source_removal = HashSet::new();
+    /// Decides the target text of every entry of `main_source`, in order.
+    pub fn translate(&self, main_source: &LangFile, extra_source: Option<&LangFile>) -> Vec<Translation> {
+        let extra_dict = extra_dict(main_source.format, extra_source);
+        let dict = self.generate_map(main_source.items.iter().map(|(_, x)| x.as_str()));
+        main_source.items.iter()
+            .map(|(key, source_text)| judge(key, source_text, dict.get(source_text).map(|x| x.as_slice()), extra_dict.get(key).copied()).0)
+            .collect()
+    }
 
-        for (key, source_text) in main_source.items.into_iter() {
-            let mut succ = false;
-            let mut succ_dict = false;
-            let mut succ_extra = false;
-
-            let mut target_text = String::default();
-            let mut target_text_dict = String::default();
-            let mut target_text_extra = String::default();
-
-            // dict
-            let config_extensions = &self.options.extensions;
-            let source_meta = GeneratorMeta {
-                namespace: key.clone(),
-                extensions: config_extensions.clone().into_iter().map(|x| x.to_str().unwrap().to_string()).collect(),
-                ..Default::default()
-            };
+    /// Like `translate`, taking the outcome of the keys unaffected by the changes since
+    /// `previous` from it, see `incremental`. The state of this run is returned as well.
+    pub fn translate_incremental(&self, main_source: &LangFile, extra_source: Option<&LangFile>, previous: Option<&ReplaceState>)
+        -> (Vec<Translation>, ReplaceState) {
+        let extra_dict = extra_dict(main_source.format, extra_source);
+        let settings = incremental::settings(&self.options);
+        let groups = incremental::group_digests(self.config, &self.options);
+        let previous = previous.filter(|x| x.settings == settings);
 
-            let dict = self.generate_map();
-            for (meta, lang_result) in dict.iter().filter(|(_, x)| x == &source_text) {
-                if meta.as_ref().eq(&source_meta) {
-                    target_text_dict = lang_result.to_string();
-                    succ_dict = true;
-                }
+        // changed groups and the groups depending on them
+        let mut changed: HashSet<String> = HashSet::new();
+        if let Some(previous) = previous {
+            changed.extend(groups.iter().filter(|(k, v)| previous.groups.get(*k) != Some(v)).map(|(k, _)| k.clone()));
+            changed.extend(previous.groups.keys().filter(|x| !groups.contains_key(*x)).cloned());
+            for group in changed.clone() {
+                changed.extend(self.graph.ancestors(&group));
             }
+        }
+        let changed_generators: Vec<usize> = (0..self.generators.len())
+            .filter(|i| self.generators[*i].meta().completed && changed.contains(&self.generators[*i].meta().group))
+            .collect();
 
-            // extra
-            if valid_extra_source {
-                if let Some((_, extra)) = extra_dict.iter().find(|(_, x)| &key == *x) {
-                    target_text_extra = extra.clone();
-                    succ_extra = true;
-                }
+        let mut produced: HashMap<&str, bool> = HashMap::new();
+        let reused: Vec<Option<&StateEntry>> = main_source.items.iter().map(|(key, source_text)| {
+            let entry = previous?.entries.get(key)?;
+            if entry.source != *source_text || entry.fallback.as_deref() != extra_dict.get(key).copied()
+                || entry.candidates.iter().any(|x| changed.contains(x)) {
+                return None;
             }
+            // a changed group may produce candidates for it now
+            let produces = *produced.entry(source_text).or_insert_with(|| {
+                changed_generators.iter().any(|i| !self.get_generator_results(*i, source_text).is_empty())
+            });
+            if produces { None } else { Some(entry) }
+        }).collect();
+        self.group_cache.borrow_mut().clear();
 
-            // judge
-            if succ_dict && succ_extra {
-                // CONFLICT
-                target_text = target_text_dict.clone();
-                succ = true;
-            } else if succ_dict && !succ_extra {
-                // REPLACED
-                target_text = target_text_dict.clone();
-                succ = true;
-            } else if !succ_dict && succ_extra {
-                // FALLBACK
-                target_text = target_text_extra.clone();
-                succ = true;
-            } else if !succ_dict && !succ_extra {
-                // FAILED
-                target_text = source_text.clone();
-                succ = false;
+        let dict = self.generate_map(main_source.items.iter().zip(reused.iter())
+            .filter(|(_, reused)| reused.is_none())
+            .map(|((_, x), _)| x.as_str()));
+
+        let mut state = ReplaceState { settings, groups, entries: HashMap::new() };
+        let mut translations = vec![];
+        for ((key, source_text), reused) in main_source.items.iter().zip(reused) {
+            let (translation, candidates) = match reused {
+                Some(entry) => (entry.to_translation(key), entry.candidates.clone()),
+                None => {
+                    let candidates = dict.get(source_text).map(|x| x.as_slice());
+                    let mut groups: Vec<String> = candidates.into_iter().flatten().map(|x| x.group.clone()).collect();
+                    groups.sort();
+                    groups.dedup();
+                    (judge(key, source_text, candidates, extra_dict.get(key).copied()).0, groups)
+                }
+            };
+            state.entries.insert(key.clone(), StateEntry {
+                source: source_text.clone(),
+                fallback: translation.fallback.clone(),
+                target: translation.target.clone(),
+                origin: translation.origin,
+                provenance: translation.provenance.clone(),
+                rivals: translation.rivals.clone(),
+                candidates,
+            });
+            translations.push(translation);
+        }
+        (translations, state)
+    }
+
+    pub fn replace(&mut self) -> std::io::Result<()> {
+        let (main_source, extra_source) = self.read_sources()?;
+        let main_format = main_source.format;
+        let translations = if self.options.incremental {
+            let state_path = incremental::state_path(&self.options);
+            let previous = ReplaceState::load(&state_path);
+            let (translations, state) = self.translate_incremental(&main_source, extra_source.as_ref(), previous.as_ref());
+            if !self.options.dry_run {
+                state.save(&state_path)?;
             }
+            translations
+        } else {
+            self.translate(&main_source, extra_source.as_ref())
+        };
+        self.save_cache()?;
 
-            main_target.items.push((key.clone(), target_text.clone()));
-            if extra_target_path.is_some() {
+        let extra_target_path = self.options.extra_target_path.as_ref();
+        let output = self.options.output();
+        if let Some(path) = self.options.conflict_report_path.as_ref() {
+            ConflictReport::new(&translations).write(&output, Path::new(path))?;
+        }
+        if let Some(path) = self.options.ambiguity_report_path.as_ref() {
+            AmbiguityReport::new(self.config, &translations).write(&output, Path::new(path))?;
+        }
+        if let Some(path) = self.options.provenance_path.as_ref() {
+            ProvenanceSidecar::new(self.config, &translations).write(&output, Path::new(path))?;
+        }
+
+        // outputs follow the layout of the source
+        let mut main_target = main_source.empty_like();
+        let mut extra_target = main_source.empty_like();
+        let mut extra_source_removal = HashSet::new();
+
+        for Translation { key, target, origin, .. } in translations.into_iter() {
+            if extra_target_path.is_some() && !origin.is_translated() {
                 // output all unknown items
-                if !succ {
-                    extra_target.items.push((key.clone(), target_text.clone()));
-                }
+                extra_target.items.push((key.clone(), target.clone()));
             }
-            if self.options.remove_redundant_fallback {
+            if self.options.remove_redundant_fallback && origin == Origin::Conflict {
                 // remove matched
-                if succ_extra && succ_dict {
-                    extra_source_removal.insert(key.clone());
-                }
+                extra_source_removal.insert(key.clone());
             }
+            main_target.items.push((key, target));
         }
 
         // write
-
-        let main_target_path = self.options.main_target_path.as_ref().expect("No target path");
-        main_target.write(main_target_path)?;
-        if let Some(extra_target_path) = extra_target_path {
-            extra_target.write(extra_target_path)?;
+        let main_target_path = Path::new(self.options.main_target_path.as_ref().expect("No target path"));
+        let main_target = main_target.into_format(self.options.target_format(main_target_path, main_format));
+        output.write(main_target_path, &main_target.to_bytes())?;
+        if let Some(extra_target_path) = extra_target_path.map(Path::new) {
+            let extra_target = extra_target.into_format(self.options.target_format(extra_target_path, main_format));
+            output.write(extra_target_path, &extra_target.to_bytes())?;
         }
         if self.options.remove_redundant_fallback {
-            let mut extra_source_modified = LangFile::default();
-            for (k, v) in extra_dict.iter() {
-                if !extra_source_removal.contains(k) {
-                    extra_source_modified.items.push((k.to_string(), v.to_string()));
-                }
-            }
-            extra_source_modified.write(self.options.extra_source_path.as_ref().unwrap())?;
+            let mut extra_source = extra_source.unwrap_or_default();
+            let extra_format = extra_source.format;
+            extra_source.items.retain(|(k, _)| !extra_source_removal.contains(&extra_format.convert_key(k, main_format)));
+            output.write(self.options.extra_source_path.as_ref().unwrap(), &extra_source.to_bytes())?;
         }
         Ok(())
     }
 
-    pub fn generate(self: &Box<Self>) -> Vec<Box<dyn LangResult<'a>>> {
+    /// Results of every completed generator whose source text is exactly `source`, with their generator.
+    pub fn generate(&self, source: &str) -> Vec<(&'a G, Box<dyn LangResult<'a>>)> {
         let mut results = vec![];
-        for x in self.generators.iter() {
+        for (i, x) in self.generators.iter().enumerate() {
             if x.meta().completed {
-                let new_results = self.get_generator_results(x);
-                if let Some(new_results) = new_results {
-                    new_results.into_iter().for_each(|x| results.push(x));
-                }
+                results.extend(self.get_generator_results(i, source).into_iter().map(|result| (*x, result)));
             }
         }
 
         results
     }
 
-    pub fn generate_map(self: &Box<Self>) -> Vec<(Cow<'a, GeneratorMeta>, String)> {
-        let mut result = vec![];
-        let lang_list = self.generate();
-        for lang_item in lang_list.into_iter() {
-            let meta: Cow<'a, GeneratorMeta> = lang_item.meta();
-            let lang_generated = lang_item.result();
-            for (_, dst) in lang_generated.into_owned().into_iter() {
-                // let meta: Cow<'a, GeneratorMeta> = lang_item.meta();
-
-                result.push((meta.clone(), dst));
+    /// Index from source text to every candidate of the completed generators in order,
+    /// built once for all `sources`.
+    pub fn generate_map<'s, I>(&self, sources: I) -> HashMap<String, Vec<Provenance>>
+        where I: IntoIterator<Item = &'s str> {
+        let mut result: HashMap<String, Vec<Provenance>> = HashMap::new();
+        for source in sources {
+            if result.contains_key(source) {
+                continue;
+            }
+            let candidates = result.entry(source.to_string()).or_default();
+            for (generator, lang_item) in self.generate(source).into_iter() {
+                candidates.extend(self.provenance(generator, lang_item.as_ref()));
             }
         }
+        // matches are only looked up while building the index
+        self.group_cache.borrow_mut().clear();
         result
     }
+
+    /// Candidates of `result` of `generator`.
+    fn provenance(&self, generator: &G, result: &(dyn LangResult<'a> + 'a)) -> Vec<Provenance> {
+        let meta = result.meta();
+        let rule = result.rule().and_then(|x| generator.meta().rule_index(x));
+        let subs = self.sub_provenance(result);
+        result.result().iter().map(|(_, target)| Provenance {
+            group: meta.group.clone(),
+            namespace: meta.namespace.clone(),
+            priority: meta.priority,
+            generator: generator.index(),
+            rule,
+            target: target.clone(),
+            subs: subs.clone(),
+        }).collect()
+    }
+
+    /// Results matched by the placeholders of `result`, with those matched by their own.
+    fn sub_provenance(&self, result: &(dyn LangResult<'a> + 'a)) -> Vec<SubProvenance> {
+        result.subs().iter().map(|sub| {
+            let meta = sub.meta();
+            let generator = sub.generator();
+            let (source, target) = sub.result().first().cloned().unwrap_or_default();
+            SubProvenance {
+                group: meta.group.clone(),
+                namespace: meta.namespace.clone(),
+                generator,
+                rule: sub.rule().and_then(|x| self.config.generators.get(generator)?.rule_index(x)),
+                source,
+                target,
+                subs: self.sub_provenance(sub.as_ref()),
+            }
+        }).collect()
+    }
+}
+
+/// Fallback entries by the keys of the main source in `main_format`.
+pub fn extra_dict(main_format: LangFormat, extra_source: Option<&LangFile>) -> HashMap<String, &str> {
+    extra_source.iter()
+        .flat_map(|x| x.items.iter().map(move |(k, v)| (x.format.convert_key(k, main_format), v.as_str())))
+        .collect()
 }
 
-impl<'a, G> GroupRepository<'a> for Box<Replacer<'a, G>> where G: Generator<'a> + Eq + Hash {
-    fn get_group_results(&self, group: &str) -> Ref<Vec<Box<(dyn LangResult<'a> + 'a)>>> {
-        if self.group_cache.borrow().contains_key(group) {
-            Ref::map(self.group_cache.borrow(), |group_cache| {
-                group_cache.get(group).unwrap()
-            })
+/// Decides the target text of the entry `key` from the candidates of its source and its fallback.
+///
+/// Of the candidates whose namespace covers the key, the one with the longest namespace name wins,
+/// then the one with the highest priority and then the last one in config order. The other
+/// candidates as specific and with the same priority as the winner but other targets are kept as
+/// its rivals.
+///
+/// A rule splitting the source more than one way yields a candidate per split, in the order of
+/// [`Template::captures`](crate::rule::Template::captures), so the split whose first placeholders
+/// capture the most wins and the other splits with other targets are its rivals.
+///
+/// The winner is returned along with the translation as the decision, if any.
+pub fn judge(key: &str, source_text: &str, candidates: Option<&[Provenance]>, extra: Option<&str>) -> (Translation, Option<Decision>) {
+    // dict
+    let matching: Vec<(usize, &Provenance)> = candidates.into_iter().flatten().enumerate()
+        .filter(|(_, x)| forge::in_namespace(key, &x.namespace))
+        .collect();
+    // typed and quoted namespaces are as specific as their names
+    let specificity = |x: &Provenance| forge::namespace_name(&x.namespace).len();
+    // the last of the maximum
+    let decision = matching.iter().max_by_key(|(_, x)| (specificity(x), x.priority)).map(|(winner, x)| {
+        let specific = matching.iter().filter(|(_, y)| specificity(y) == specificity(x)).count();
+        let tied = matching.iter().filter(|(_, y)| specificity(y) == specificity(x) && y.priority == x.priority).count();
+        let reason = if matching.len() == 1 {
+            Reason::Only
+        } else if specific == 1 {
+            Reason::MostSpecific { covering: matching.len() }
+        } else if tied == 1 {
+            Reason::Priority { specific }
         } else {
-            let mut results = vec![];
-            for gen in self.generators.clone().into_iter() {
-                if &gen.meta().group == group {
-                    if let Some(new_results) = self.get_generator_results(gen.clone()) {
-                        new_results.into_iter().for_each(|x| results.push(x));
-                    }
-                }
+            Reason::ConfigOrder { tied }
+        };
+        Decision { winner: *winner, reason }
+    });
+    let provenance = decision.map(|x| candidates.unwrap_or_default()[x.winner].clone());
+    let mut rivals: Vec<Provenance> = vec![];
+    if let Some(winner) = provenance.as_ref() {
+        let tied = |x: &Provenance| specificity(x) == specificity(winner) && x.priority == winner.priority;
+        for (_, x) in matching.iter().copied().filter(|(_, x)| tied(x) && x.target != winner.target) {
+            // the same target of the same definition through other subs is no other rival
+            let same = |y: &Provenance| (y.generator, y.rule, &y.namespace, &y.target) == (x.generator, x.rule, &x.namespace, &x.target);
+            if !rivals.iter().any(same) {
+                rivals.push(x.clone());
             }
-            self.group_cache.borrow_mut().insert(group.to_string(), results);
-            Ref::map(self.group_cache.borrow(), |group_cache| {
-                group_cache.get(group).unwrap()
-            })
         }
     }
-}
\ No newline at end of file
+
+    // judge
+    let (target, origin) = match (provenance.as_ref(), extra) {
+        // CONFLICT
+        (Some(dict), Some(_)) => (dict.target.clone(), Origin::Conflict),
+        // REPLACED
+        (Some(dict), None) => (dict.target.clone(), Origin::Replaced),
+        // FALLBACK
+        (None, Some(extra)) => (extra.to_string(), Origin::Fallback),
+        // FAILED
+        (None, None) => (source_text.to_string(), Origin::Failed),
+    };
+
+    let translation = Translation {
+        key: key.to_string(),
+        source: source_text.to_string(),
+        target,
+        origin,
+        provenance,
+        rivals,
+        fallback: extra.map(|x| x.to_string()),
+    };
+    (translation, decision)
+}
+
+impl<'a, G> GroupRepository<'a> for Replacer<'a, G> where G: Generator<'a> {
+    fn get_group_results(&self, group: &str, source: &str) -> Vec<Box<dyn LangResult<'a>>> {
+        let cache_key = (group.to_string(), source.to_string());
+        if let Some(gcache_found) = self.group_cache.borrow().get(&cache_key) {
+            // old group
+            return gcache_found.clone();
+        }
+        // new group
+        let mut results = vec![];
+        if let Some(indexes) = self.groups.get(group) {
+            for index in indexes.iter() {
+                results.extend(self.get_generator_results(*index, source));
+            }
+        }
+        self.group_cache.borrow_mut().insert(cache_key, results.clone());
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use super::*;
+
+    fn candidate(generator: usize, namespace: &str, priority: i32, target: &str) -> Provenance {
+        Provenance {
+            group: "g".to_string(),
+            namespace: namespace.to_string(),
+            priority,
+            generator,
+            rule: None,
+            target: target.to_string(),
+            subs: vec![],
+        }
+    }
+
+    fn decide(key: &str, candidates: &[Provenance]) -> (Translation, Option<Decision>) {
+        judge(key, "source", Some(candidates), None)
+    }
+
+    /// Candidates of `source` generated by the config `yaml`.
+    fn generate(yaml: &str, source: &str) -> Vec<Provenance> {
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let options = RuntimeOptions::parse_from(["gt6tg", "--no-cache"]);
+        let generators = config.generators();
+        let replacer = Replacer::new(&config, options, generators.iter().collect()).unwrap();
+        replacer.generate_map([source]).remove(source).unwrap_or_default()
+    }
+
+    #[test]
+    fn most_specific_namespace_wins() {
+        let candidates = [candidate(0, "S:oredict.plate", 0, "a"), candidate(1, "S:oredict.", 5, "b")];
+        let (translation, decision) = decide("S:oredict.plateIron.name", &candidates);
+        assert_eq!(translation.target, "a");
+        assert_eq!(translation.origin, Origin::Replaced);
+        assert_eq!(decision, Some(Decision { winner: 0, reason: Reason::MostSpecific { covering: 2 } }));
+        assert!(translation.rivals.is_empty());
+    }
+
+    #[test]
+    fn specificity_compares_namespace_names() {
+        let candidates = [candidate(0, "oredict.abc", 0, "a"), candidate(1, "S:oredict.ab", 0, "b")];
+        let (translation, _) = decide("S:oredict.abcd", &candidates);
+        assert_eq!(translation.target, "a");
+        let candidates = [candidate(0, "S:\"Dirty W", 0, "a"), candidate(1, "Dirty", 0, "b")];
+        let (translation, _) = decide("Dirty Water.name", &candidates);
+        assert_eq!(translation.target, "a");
+    }
+
+    #[test]
+    fn uncovering_candidates_are_ignored() {
+        let candidates = [candidate(0, "S:gt.", 9, "a"), candidate(1, "", 0, "b")];
+        let (translation, decision) = decide("S:oredict.x", &candidates);
+        assert_eq!(translation.target, "b");
+        assert_eq!(decision, Some(Decision { winner: 1, reason: Reason::Only }));
+        let (translation, decision) = judge("S:oredict.x", "source", Some(&candidates[..1]), Some("fallback"));
+        assert_eq!((translation.target.as_str(), translation.origin, decision), ("fallback", Origin::Fallback, None));
+    }
+
+    #[test]
+    fn priority_breaks_ties_of_specificity() {
+        let candidates = [candidate(0, "S:oredict.", 1, "a"), candidate(1, "oredict.", 0, "b")];
+        let (translation, decision) = decide("S:oredict.x", &candidates);
+        assert_eq!(translation.target, "a");
+        assert_eq!(decision, Some(Decision { winner: 0, reason: Reason::Priority { specific: 2 } }));
+        assert!(translation.rivals.is_empty());
+    }
+
+    #[test]
+    fn config_order_breaks_ties_of_priority() {
+        let candidates = [candidate(0, "S:oredict.", 0, "a"), candidate(1, "S:oredict.", 0, "b"), candidate(2, "S:oredict.", 0, "b")];
+        let (translation, decision) = decide("S:oredict.x", &candidates);
+        assert_eq!(translation.target, "b");
+        assert_eq!(translation.provenance.unwrap().generator, 2);
+        assert_eq!(decision, Some(Decision { winner: 2, reason: Reason::ConfigOrder { tied: 3 } }));
+        assert_eq!(translation.rivals, vec![candidates[0].clone()]);
+    }
+
+    #[test]
+    fn rivals_are_deduplicated_regardless_of_subs() {
+        let sub = |target: &str| SubProvenance {
+            group: "material".to_string(),
+            namespace: String::new(),
+            generator: 3,
+            rule: None,
+            source: "Iron".to_string(),
+            target: target.to_string(),
+            subs: vec![],
+        };
+        let mut first = candidate(0, "", 0, "a");
+        first.subs = vec![sub("铁")];
+        let mut second = first.clone();
+        second.subs = vec![sub("鐵")];
+        let candidates = [first.clone(), second, candidate(1, "", 0, "b")];
+        let (translation, _) = decide("S:x", &candidates);
+        assert_eq!(translation.target, "b");
+        assert_eq!(translation.rivals, vec![first]);
+    }
+
+    #[test]
+    fn typed_and_untyped_namespaces_combine() {
+        let yaml = r#"
+generators:
+  - group: material
+    namespace: "S:oredict."
+    completed: false
+    dict: { Iron: 铁 }
+  - group: plate
+    namespace: oredict.plateDouble
+    rules:
+      - { s: "Double {0} Plate", t: "双层{0}板", subs: [material] }
+"#;
+        let candidates = generate(yaml, "Double Iron Plate");
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].namespace, "S:oredict.plateDouble");
+        let (translation, _) = decide("S:oredict.plateDoubleIron.name", &candidates);
+        assert_eq!(translation.target, "双层铁板");
+        let (translation, _) = decide("oredict.plateDoubleIron.name", &candidates);
+        assert_eq!(translation.target, "双层铁板");
+    }
+
+    #[test]
+    fn high_priority_subs_do_not_outrank_specific_rules() {
+        let yaml = r#"
+generators:
+  - group: material
+    priority: 10
+    completed: false
+    dict: { Iron: 铁 }
+  - group: generic
+    namespace: "S:oredict."
+    rules:
+      - { s: "{0} Plate", t: "{0}板", subs: [material] }
+  - group: plate
+    namespace: "S:oredict.plate"
+    rules:
+      - { s: "{0} Plate", t: "{0}板材", subs: [material] }
+"#;
+        let candidates = generate(yaml, "Iron Plate");
+        assert_eq!(candidates.iter().map(|x| x.priority).collect::<Vec<_>>(), vec![0, 0]);
+        let (translation, decision) = decide("S:oredict.plateIron.name", &candidates);
+        assert_eq!(translation.target, "铁板材");
+        assert_eq!(decision, Some(Decision { winner: 1, reason: Reason::MostSpecific { covering: 2 } }));
+    }
+
+    #[test]
+    fn ambiguous_splits_of_a_rule_are_rivals() {
+        let yaml = r#"
+generators:
+  - group: word
+    completed: false
+    dict: { Double: 双层, Double Iron: 双铁, Iron Plate: 铁板, Plate: 板 }
+  - group: item
+    rules:
+      - { s: "{0} {1}", t: "{0}{1}", subs: [word, word] }
+"#;
+        let candidates = generate(yaml, "Double Iron Plate");
+        let targets: Vec<&str> = candidates.iter().map(|x| x.target.as_str()).collect();
+        assert_eq!(targets, vec!["双层铁板", "双铁板"]);
+        let (translation, decision) = decide("S:x", &candidates);
+        assert_eq!(translation.target, "双铁板");
+        assert_eq!(decision, Some(Decision { winner: 1, reason: Reason::ConfigOrder { tied: 2 } }));
+        assert_eq!(translation.rivals, vec![candidates[0].clone()]);
+    }
+}
@@ -0,0 +1,242 @@
+//! Reports on the outcome of replacing, as YAML, or as JSON for `.json` files:
+//!
+//! - conflicts: the keys both generated and in the fallback
+//! - ambiguities: the keys with candidates as specific as the winner but with other targets
+//!
+//! The provenance sidecar attributes every output key to where its target comes from, as JSON Lines.
+use std::path::Path;
+use serde::Serialize;
+use crate::config::Config;
+use crate::output::Output;
+use crate::result::{Origin, Provenance, SubProvenance, Translation};
+
+/// Like `config.yml: generators[3].rules[0]`, without the rule for dict entries.
+pub fn location(config: &Config, generator: usize, rule: Option<usize>) -> String {
+    let mut location = config.generator_location(generator);
+    if let Some(rule) = rule {
+        location.push_str(&format!(".rules[{}]", rule));
+    }
+    location
+}
+
+/// Renders `report` as JSON for `.json` files and YAML otherwise.
+fn render<T: Serialize>(report: &T, path: &Path) -> Vec<u8> {
+    match path.extension().and_then(|x| x.to_str()) {
+        Some(x) if x.eq_ignore_ascii_case("json") => {
+            let mut output = serde_json::to_vec_pretty(report).expect("strings are valid JSON");
+            output.push(b'\n');
+            output
+        }
+        _ => serde_yaml::to_string(report).expect("strings are valid YAML").into_bytes(),
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub key: String,
+    pub source: String,
+    /// Target written to the output
+    pub generated: String,
+    pub fallback: String,
+    /// Group of the generator that won
+    pub group: String,
+    pub namespace: String,
+    /// Whether the fallback says the same, making the entry redundant
+    pub same: bool,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConflictReport {
+    /// Number of conflicts
+    pub total: usize,
+    /// Number of conflicts where the fallback disagrees
+    pub different: usize,
+    /// Every conflict in the order of the main source
+    pub conflicts: Vec<Conflict>,
+}
+
+impl ConflictReport {
+    pub fn new(translations: &[Translation]) -> Self {
+        let conflicts: Vec<Conflict> = translations.iter()
+            .filter(|x| x.origin == Origin::Conflict)
+            .map(|x| {
+                let fallback = x.fallback.clone().unwrap_or_default();
+                Conflict {
+                    key: x.key.clone(),
+                    source: x.source.clone(),
+                    same: fallback == x.target,
+                    generated: x.target.clone(),
+                    fallback,
+                    group: x.provenance.as_ref().map(|x| x.group.clone()).unwrap_or_default(),
+                    namespace: x.provenance.as_ref().map(|x| x.namespace.clone()).unwrap_or_default(),
+                }
+            })
+            .collect();
+        ConflictReport {
+            total: conflicts.len(),
+            different: conflicts.iter().filter(|x| !x.same).count(),
+            conflicts,
+        }
+    }
+
+    pub fn write(&self, output: &Output, path: &Path) -> std::io::Result<()> {
+        output.write(path, &render(self, path))
+    }
+}
+
+/// A candidate target with the definition it comes from.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub target: String,
+    pub group: String,
+    pub namespace: String,
+    pub priority: i32,
+    /// Like `config.yml: generators[3].rules[0]`
+    pub location: String,
+}
+
+impl Candidate {
+    pub fn new(config: &Config, provenance: &Provenance) -> Self {
+        Candidate {
+            target: provenance.target.clone(),
+            group: provenance.group.clone(),
+            namespace: provenance.namespace.clone(),
+            priority: provenance.priority,
+            location: location(config, provenance.generator, provenance.rule),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Ambiguity {
+    pub key: String,
+    pub source: String,
+    /// Candidate written to the output
+    pub chosen: Candidate,
+    /// Candidates as specific and with the same priority but other targets, overruled by config order
+    pub rivals: Vec<Candidate>,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct AmbiguityReport {
+    /// Number of ambiguous keys
+    pub total: usize,
+    /// Every ambiguous key in the order of the main source
+    pub ambiguities: Vec<Ambiguity>,
+}
+
+impl AmbiguityReport {
+    pub fn new(config: &Config, translations: &[Translation]) -> Self {
+        let ambiguities: Vec<Ambiguity> = translations.iter()
+            .filter(|x| !x.rivals.is_empty())
+            .filter_map(|x| Some(Ambiguity {
+                key: x.key.clone(),
+                source: x.source.clone(),
+                chosen: Candidate::new(config, x.provenance.as_ref()?),
+                rivals: x.rivals.iter().map(|r| Candidate::new(config, r)).collect(),
+            }))
+            .collect();
+        AmbiguityReport { total: ambiguities.len(), ambiguities }
+    }
+
+    pub fn write(&self, output: &Output, path: &Path) -> std::io::Result<()> {
+        output.write(path, &render(self, path))
+    }
+}
+
+/// A result matched by a placeholder and where it comes from.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SubAttribution {
+    pub group: String,
+    pub namespace: String,
+    /// Text matched by the placeholder
+    pub source: String,
+    pub target: String,
+    /// Position of the generator in `Config::generators`
+    pub generator: usize,
+    /// Position of the rule in the generator, absent for dict entries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule: Option<usize>,
+    /// Like `config.yml: generators[3].rules[0]`
+    pub location: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub subs: Vec<SubAttribution>,
+}
+
+impl SubAttribution {
+    pub fn new(config: &Config, sub: &SubProvenance) -> Self {
+        SubAttribution {
+            group: sub.group.clone(),
+            namespace: sub.namespace.clone(),
+            source: sub.source.clone(),
+            target: sub.target.clone(),
+            generator: sub.generator,
+            rule: sub.rule,
+            location: location(config, sub.generator, sub.rule),
+            subs: sub.subs.iter().map(|x| SubAttribution::new(config, x)).collect(),
+        }
+    }
+}
+
+/// Where the target of an output key comes from, a line of the provenance sidecar.
+///
+/// Generated targets have the generator and the results matched by the placeholders of
+/// the rule, `fallback` and `untranslated` ones only the key and the origin.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Attribution {
+    pub key: String,
+    pub origin: Origin,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    /// Position of the generator in `Config::generators`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generator: Option<usize>,
+    /// Position of the rule in the generator, absent for dict entries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule: Option<usize>,
+    /// Like `config.yml: generators[3].rules[0]`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub subs: Vec<SubAttribution>,
+}
+
+impl Attribution {
+    pub fn new(config: &Config, translation: &Translation) -> Self {
+        let provenance = translation.provenance.as_ref();
+        Attribution {
+            key: translation.key.clone(),
+            origin: translation.origin,
+            group: provenance.map(|x| x.group.clone()),
+            namespace: provenance.map(|x| x.namespace.clone()),
+            generator: provenance.map(|x| x.generator),
+            rule: provenance.and_then(|x| x.rule),
+            location: provenance.map(|x| location(config, x.generator, x.rule)),
+            subs: provenance.iter().flat_map(|x| x.subs.iter()).map(|x| SubAttribution::new(config, x)).collect(),
+        }
+    }
+}
+
+/// Attribution of every output key in the order of the main source.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProvenanceSidecar {
+    pub attributions: Vec<Attribution>,
+}
+
+impl ProvenanceSidecar {
+    pub fn new(config: &Config, translations: &[Translation]) -> Self {
+        ProvenanceSidecar { attributions: translations.iter().map(|x| Attribution::new(config, x)).collect() }
+    }
+
+    /// Writes one attribution per line as JSON.
+    pub fn write(&self, output: &Output, path: &Path) -> std::io::Result<()> {
+        let mut contents = vec![];
+        for attribution in self.attributions.iter() {
+            serde_json::to_writer(&mut contents, attribution).expect("strings are valid JSON");
+            contents.push(b'\n');
+        }
+        output.write(path, &contents)
+    }
+}
@@ -0,0 +1,96 @@
+//! Outcome of replacing the entries of the main source, see `Replacer::translate`.
+use serde::{Deserialize, Serialize};
+
+/// Where the target text of an entry comes from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Origin {
+    /// Generated, the fallback has a translation as well
+    Conflict,
+    /// Generated
+    Replaced,
+    /// Taken from the fallback
+    Fallback,
+    /// Neither generated nor in the fallback, the target is the source
+    #[serde(rename = "untranslated")]
+    Failed,
+}
+
+impl Origin {
+    pub fn is_generated(self) -> bool {
+        matches!(self, Origin::Conflict | Origin::Replaced)
+    }
+
+    pub fn is_translated(self) -> bool {
+        self != Origin::Failed
+    }
+}
+
+/// A target generated for a source text and where it comes from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    pub group: String,
+    /// Namespace of the generator, narrowed by the subs
+    pub namespace: String,
+    /// Priority of the generator or the rule
+    pub priority: i32,
+    /// Position of the generator in `Config::generators`
+    pub generator: usize,
+    /// Position of the rule in the generator, `None` for dict entries
+    pub rule: Option<usize>,
+    pub target: String,
+    /// Results matched by the placeholders of the rule, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subs: Vec<SubProvenance>,
+}
+
+/// A result matched by a placeholder of a rule and where it comes from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SubProvenance {
+    pub group: String,
+    pub namespace: String,
+    /// Position of the generator in `Config::generators`
+    pub generator: usize,
+    /// Position of the rule in the generator, `None` for dict entries
+    pub rule: Option<usize>,
+    /// Text matched by the placeholder
+    pub source: String,
+    pub target: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subs: Vec<SubProvenance>,
+}
+
+/// Why the winner of the candidates of a key was chosen, see `replacer::judge`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// The only candidate whose namespace covers the key
+    Only,
+    /// The most specific namespace of the `covering` candidates covering the key
+    MostSpecific { covering: usize },
+    /// The highest priority of the `specific` candidates as specific
+    Priority { specific: usize },
+    /// The last in config order of the `tied` candidates as specific and with the same priority
+    ConfigOrder { tied: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    /// Position of the winner among the candidates
+    pub winner: usize,
+    pub reason: Reason,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Translation {
+    /// Key in the form of the main source
+    pub key: String,
+    pub source: String,
+    pub target: String,
+    pub origin: Origin,
+    /// The winning candidate of a generated target
+    pub provenance: Option<Provenance>,
+    /// Candidates as specific as the winner with other targets, see `Replacer::translate`
+    pub rivals: Vec<Provenance>,
+    /// Fallback entry of the key, whether used or not
+    pub fallback: Option<String>,
+}
@@ -7,5 +7,205 @@ pub struct Rule {
     #[serde(rename = "t")]
     pub target: String,
 
-    pub subs: Vec<String>
+    pub subs: Vec<String>,
+
+    /// Priority of the results of this rule instead of that of the generator
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+}
+
+impl Rule {
+    pub fn source_template(&self) -> Template {
+        Template::parse(&self.source)
+    }
+
+    pub fn target_template(&self) -> Template {
+        Template::parse(&self.target)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Segment {
+    Text(String),
+    /// `{n}`, bound to the n-th entry of `Rule::subs`
+    Placeholder(usize),
+}
+
+/// A parsed rule template like `"{1}的{0}"`.
+///
+/// Placeholders may appear in any order, any number of times, or not at all.
+/// `{{` and `}}` stand for literal braces.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Template {
+    pub segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn parse(fmt: &str) -> Self {
+        let mut segments = vec![];
+        let mut text = String::new();
+        let mut rest = fmt;
+        while let Some(c) = rest.chars().next() {
+            if rest.starts_with("{{") || rest.starts_with("}}") {
+                text.push(c);
+                rest = &rest[2..];
+                continue;
+            }
+            if c == '{' {
+                if let Some(end) = rest.find('}') {
+                    if let Ok(index) = rest[1..end].parse::<usize>() {
+                        if !text.is_empty() {
+                            segments.push(Segment::Text(std::mem::take(&mut text)));
+                        }
+                        segments.push(Segment::Placeholder(index));
+                        rest = &rest[end + 1..];
+                        continue;
+                    }
+                }
+            }
+            text.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        Template { segments }
+    }
+
+    /// Fills every `{n}` with `args[n]`, placeholders without an argument are kept as is.
+    pub fn format<S: AsRef<str>>(&self, args: &[S]) -> String {
+        let mut result = String::new();
+        for segment in self.segments.iter() {
+            match segment {
+                Segment::Text(text) => result.push_str(text),
+                Segment::Placeholder(index) => match args.get(*index) {
+                    Some(arg) => result.push_str(arg.as_ref()),
+                    None => {
+                        result.push('{');
+                        result.push_str(&index.to_string());
+                        result.push('}');
+                    }
+                },
+            }
+        }
+        result
+    }
+
+    /// Parses `text` against this template.
+    ///
+    /// Returns every way of splitting `text` into the literal segments and
+    /// non-empty placeholder values, indexed by placeholder. A placeholder
+    /// used more than once must capture the same text each time.
+    pub fn captures<'t>(&self, text: &'t str) -> Vec<Vec<Option<&'t str>>> {
+        let size = self.placeholders().max().map_or(0, |x| x + 1);
+        let mut bindings = vec![None; size];
+        let mut results = vec![];
+        self.capture_from(0, text, &mut bindings, &mut results);
+        results
+    }
+
+    fn capture_from<'t>(&self, segment: usize, text: &'t str, bindings: &mut Vec<Option<&'t str>>, results: &mut Vec<Vec<Option<&'t str>>>) {
+        match self.segments.get(segment) {
+            None => {
+                if text.is_empty() {
+                    results.push(bindings.clone());
+                }
+            }
+            Some(Segment::Text(literal)) => {
+                if let Some(rest) = text.strip_prefix(literal.as_str()) {
+                    self.capture_from(segment + 1, rest, bindings, results);
+                }
+            }
+            Some(Segment::Placeholder(index)) => {
+                if let Some(bound) = bindings[*index] {
+                    // repeated placeholder
+                    if let Some(rest) = text.strip_prefix(bound) {
+                        self.capture_from(segment + 1, rest, bindings, results);
+                    }
+                    return;
+                }
+                let ends: Vec<usize> = match self.segments.get(segment + 1) {
+                    None => vec![text.len()],
+                    Some(Segment::Text(literal)) => text.char_indices()
+                        .map(|(i, _)| i)
+                        .filter(|i| text[*i..].starts_with(literal.as_str()))
+                        .collect(),
+                    Some(Segment::Placeholder(_)) => text.char_indices().map(|(i, _)| i).collect(),
+                };
+                for end in ends.into_iter().filter(|x| *x > 0) {
+                    bindings[*index] = Some(&text[..end]);
+                    self.capture_from(segment + 1, &text[end..], bindings, results);
+                }
+                bindings[*index] = None;
+            }
+        }
+    }
+
+    pub fn placeholders(&self) -> impl Iterator<Item = usize> + '_ {
+        self.segments.iter().filter_map(|x| match x {
+            Segment::Placeholder(index) => Some(*index),
+            Segment::Text(_) => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn captures<'t>(template: &str, text: &'t str) -> Vec<Vec<Option<&'t str>>> {
+        Template::parse(template).captures(text)
+    }
+
+    #[test]
+    fn reordered_placeholders() {
+        let template = Template::parse("{1}的{0}");
+        assert_eq!(template.segments, vec![
+            Segment::Placeholder(1),
+            Segment::Text("的".to_string()),
+            Segment::Placeholder(0),
+        ]);
+        assert_eq!(template.format(&["Plate", "Iron"]), "Iron的Plate");
+        assert_eq!(template.captures("铁的板"), vec![vec![Some("板"), Some("铁")]]);
+    }
+
+    #[test]
+    fn repeated_placeholder_captures_the_same_text() {
+        assert_eq!(captures("{0} and {0}", "Iron and Iron"), vec![vec![Some("Iron")]]);
+        assert!(captures("{0} and {0}", "Iron and Gold").is_empty());
+    }
+
+    #[test]
+    fn adjacent_placeholders() {
+        assert_eq!(captures("{0}{1}", "abc"), vec![
+            vec![Some("a"), Some("bc")],
+            vec![Some("ab"), Some("c")],
+        ]);
+        assert!(captures("{0}{1}", "a").is_empty());
+    }
+
+    #[test]
+    fn escaped_braces() {
+        let template = Template::parse("{{{0}}}");
+        assert_eq!(template.segments, vec![
+            Segment::Text("{".to_string()),
+            Segment::Placeholder(0),
+            Segment::Text("}".to_string()),
+        ]);
+        assert_eq!(template.captures("{Iron}"), vec![vec![Some("Iron")]]);
+        assert_eq!(template.format(&["铁"]), "{铁}");
+    }
+
+    #[test]
+    fn multibyte_literals() {
+        assert_eq!(captures("{0}（{1}）", "机器（燃料）"), vec![vec![Some("机器"), Some("燃料")]]);
+        assert_eq!(captures("§e{0}", "§e铁"), vec![vec![Some("铁")]]);
+    }
+
+    #[test]
+    fn unmatched_literal() {
+        assert!(captures("{0} Plate", "Iron Ingot").is_empty());
+        assert!(captures("Double {0}", "Iron").is_empty());
+        assert!(captures("{0} Plate", " Plate").is_empty());
+    }
 }
@@ -0,0 +1,199 @@
+//! Review sheets: CSV, or TSV for `.tsv` files, with the columns
+//!
+//! | key | english | translation | origin | notes |
+//! |-----|---------|-------------|--------|-------|
+//!
+//! The origin is `group:<name>` for generated entries, `fallback` or `none`.
+//! Sheets are written with a BOM so that spreadsheets read them as UTF-8,
+//! keeping `§` formatting codes intact. Values with delimiters, quotes or line
+//! breaks are quoted.
+//!
+//! Importing merges the edited translations of the fallback and untranslated
+//! rows into the fallback, generated rows are left to the config and listed
+//! in the report when edited.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use csv::{QuoteStyle, ReaderBuilder, WriterBuilder};
+use crate::config::RuntimeOptions;
+use crate::exchange::{self, invalid_data, ImportEntry, ImportReport};
+use crate::forge::ParseError;
+use crate::result::{Origin, Translation};
+
+const HEADER: [&str; 5] = ["key", "english", "translation", "origin", "notes"];
+
+/// `GregTech.review.csv` in the folder of the language.
+pub fn default_path(options: &RuntimeOptions) -> PathBuf {
+    Path::new(&options.workplace_path).join(&options.lang).join("GregTech.review.csv")
+}
+
+fn delimiter(path: &Path) -> u8 {
+    match path.extension().and_then(|x| x.to_str()) {
+        Some(x) if x.eq_ignore_ascii_case("tsv") => b'\t',
+        _ => b',',
+    }
+}
+
+fn origin_name(translation: &Translation) -> String {
+    match translation.origin {
+        Origin::Conflict | Origin::Replaced => format!("group:{}", translation.provenance.as_ref().map(|x| x.group.as_str()).unwrap_or_default()),
+        Origin::Fallback => "fallback".to_string(),
+        Origin::Failed => "none".to_string(),
+    }
+}
+
+/// Renders `translations` as a sheet separated by `delimiter`.
+pub fn render(delimiter: u8, translations: &[Translation]) -> Vec<u8> {
+    let mut writer = WriterBuilder::new()
+        .delimiter(delimiter)
+        .quote_style(QuoteStyle::Necessary)
+        .from_writer("\u{feff}".as_bytes().to_vec());
+    let write = |writer: &mut csv::Writer<Vec<u8>>| -> csv::Result<()> {
+        writer.write_record(HEADER)?;
+        for translation in translations.iter() {
+            writer.write_record([
+                translation.key.as_str(),
+                &translation.source,
+                &translation.target,
+                &origin_name(translation),
+                "",
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    };
+    write(&mut writer).expect("writing to memory");
+    writer.into_inner().expect("writing to memory")
+}
+
+/// Exports the outcome of replacing to `path`.
+pub fn export(options: &RuntimeOptions, translations: &[Translation], path: &Path) -> std::io::Result<()> {
+    options.output().write(path, &render(delimiter(path), translations))
+}
+
+/// Reads the rows of a sheet separated by `delimiter`, as entries located at `file:line`.
+///
+/// Generated rows are marked `generated`.
+pub fn parse(file: &str, delimiter: u8, text: &str) -> Result<Vec<ImportEntry>, ParseError> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let error = |e: csv::Error| ParseError {
+        line: e.position().map_or(0, |x| x.line() as usize),
+        message: e.to_string(),
+    };
+    let mut reader = ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let headers = reader.headers().map_err(error)?.clone();
+    let column = |name: &str| headers.iter().position(|x| x.trim().eq_ignore_ascii_case(name))
+        .ok_or_else(|| ParseError { line: 1, message: format!("missing column '{}'", name) });
+    let (key, english, translation, origin) = (column("key")?, column("english")?, column("translation")?, column("origin")?);
+
+    let mut entries = vec![];
+    for record in reader.records() {
+        let record = record.map_err(error)?;
+        let field = |i: usize| record.get(i).unwrap_or_default().to_string();
+        let origin = field(origin);
+        let (source, mut target) = (field(english), field(translation));
+        if origin == "none" && target == source {
+            // left untranslated
+            target.clear();
+        }
+        entries.push(ImportEntry {
+            position: format!("{}:{}", file, record.position().map_or(0, |x| x.line())),
+            key: Some(field(key)).filter(|x| !x.is_empty()),
+            source,
+            target,
+            fuzzy: false,
+            obsolete: false,
+            generated: origin.starts_with("group:"),
+        });
+    }
+    Ok(entries)
+}
+
+/// Merges the edited rows of `files` into the fallback, generated rows differing from
+/// their target in `translations` are reported.
+pub fn import<P: AsRef<Path>>(options: &RuntimeOptions, translations: &[Translation], files: &[P]) -> std::io::Result<ImportReport> {
+    let generated: HashMap<&str, &str> = translations.iter()
+        .filter(|x| x.origin.is_generated())
+        .map(|x| (x.key.as_str(), x.target.as_str()))
+        .collect();
+    let mut imported = vec![];
+    for file in files {
+        let file = file.as_ref();
+        let text = std::fs::read_to_string(file)?;
+        let entries = parse(&file.display().to_string(), delimiter(file), &text).map_err(|e| invalid_data(file, e))?;
+        // generated rows left as they were
+        imported.extend(entries.into_iter().filter(|x| {
+            !x.generated || x.key.as_deref().and_then(|k| generated.get(k)) != Some(&x.target.as_str())
+        }));
+    }
+    exchange::import(options, imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translation(key: &str, source: &str, target: &str, origin: Origin) -> Translation {
+        Translation {
+            key: key.to_string(),
+            source: source.to_string(),
+            target: target.to_string(),
+            origin,
+            provenance: None,
+            rivals: vec![],
+            fallback: None,
+        }
+    }
+
+    fn translations() -> Vec<Translation> {
+        vec![
+            translation("S:a", "Iron, Plate", "铁，板", Origin::Fallback),
+            translation("S:\"Dirty Water.name\"", "Say \"hi\"", "说“嗨”\"", Origin::Fallback),
+            translation("S:c", "Tab\there", "制表\t符", Origin::Fallback),
+            translation("S:d", "§6Gold§r Plate", "§6金§r板", Origin::Replaced),
+            translation("S:e", "Two\nLines", "Two\nLines", Origin::Failed),
+        ]
+    }
+
+    /// Key, English and translation of the rows of `text`.
+    fn rows(delimiter: u8, text: &[u8]) -> Vec<(Option<String>, String, String, bool)> {
+        parse("test", delimiter, std::str::from_utf8(text).unwrap()).unwrap().into_iter()
+            .map(|x| (x.key, x.source, x.target, x.generated))
+            .collect()
+    }
+
+    fn expected() -> Vec<(Option<String>, String, String, bool)> {
+        translations().into_iter().map(|x| {
+            let generated = x.origin.is_generated();
+            // untranslated rows import nothing unless edited
+            let target = if x.origin == Origin::Failed { String::new() } else { x.target };
+            (Some(x.key), x.source, target, generated)
+        }).collect()
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let text = render(b',', &translations());
+        assert!(text.starts_with("\u{feff}key,english,translation,origin,notes\n".as_bytes()));
+        let text_str = std::str::from_utf8(&text).unwrap();
+        assert!(text_str.contains("S:a,\"Iron, Plate\",铁，板,fallback,\n"));
+        assert!(text_str.contains("\"S:\"\"Dirty Water.name\"\"\",\"Say \"\"hi\"\"\""));
+        assert!(text_str.contains("S:c,Tab\there,制表\t符,fallback,\n"));
+        assert!(text_str.contains("S:d,§6Gold§r Plate,§6金§r板,group:,\n"));
+        assert_eq!(rows(b',', &text), expected());
+    }
+
+    #[test]
+    fn tsv_round_trip() {
+        let text = render(b'\t', &translations());
+        let text_str = std::str::from_utf8(&text).unwrap();
+        assert!(text_str.contains("S:a\tIron, Plate\t铁，板\tfallback\t\n"));
+        assert!(text_str.contains("S:c\t\"Tab\there\"\t\"制表\t符\"\tfallback\t\n"));
+        assert!(text_str.contains("S:d\t§6Gold§r Plate\t§6金§r板\tgroup:\t\n"));
+        assert_eq!(rows(b'\t', &text), expected());
+        assert_eq!(delimiter(Path::new("a/GregTech.review.TSV")), b'\t');
+        assert_eq!(delimiter(Path::new("a/GregTech.review.csv")), b',');
+    }
+}
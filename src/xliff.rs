@@ -0,0 +1,327 @@
+//! XLIFF 1.2 and 2.0 for CAT tools.
+//!
+//! Every entry of the main source is a unit named by its lang key, the state
+//! tells where the target comes from:
+//!
+//! | entry        | 1.2 target state    | 2.0 segment state |
+//! |--------------|---------------------|-------------------|
+//! | generated    | `final`             | `final`           |
+//! | fallback     | `translated`        | `translated`      |
+//! | untranslated | `needs-translation` | `initial`         |
+//!
+//! Generated units are marked `translate="no"` as they are overwritten by the
+//! generators anyway. Importing merges the other translated units into the
+//! fallback, matched by name so that the order of units does not matter.
+use std::path::{Path, PathBuf};
+use clap::ArgEnum;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::escape::escape;
+use quick_xml::Reader;
+use crate::config::RuntimeOptions;
+use crate::exchange::{self, invalid_data, ImportEntry, ImportReport};
+use crate::forge::ParseError;
+use crate::result::{Origin, Translation};
+
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XliffVersion {
+    #[clap(name = "1.2")]
+    V1_2,
+    #[clap(name = "2.0")]
+    V2_0,
+}
+
+/// `GregTech.xlf` in the folder of the language.
+pub fn default_path(options: &RuntimeOptions) -> PathBuf {
+    Path::new(&options.workplace_path).join(&options.lang).join("GregTech.xlf")
+}
+
+fn origin_name(origin: Origin) -> &'static str {
+    match origin {
+        Origin::Conflict | Origin::Replaced => "generated",
+        Origin::Fallback => "fallback",
+        Origin::Failed => "untranslated",
+    }
+}
+
+/// Renders `translations` as an XLIFF document.
+pub fn render(version: XliffVersion, original: &str, target_lang: &str, translations: &[Translation]) -> Vec<u8> {
+    let mut output = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let original = escape(original);
+    let target_lang = escape(target_lang);
+    match version {
+        XliffVersion::V1_2 => {
+            output.push_str("<xliff version=\"1.2\" xmlns=\"urn:oasis:names:tc:xliff:document:1.2\">\n");
+            output.push_str(&format!("  <file original=\"{}\" source-language=\"en\" target-language=\"{}\" datatype=\"plaintext\">\n", original, target_lang));
+            output.push_str("    <body>\n");
+        }
+        XliffVersion::V2_0 => {
+            output.push_str(&format!("<xliff version=\"2.0\" xmlns=\"urn:oasis:names:tc:xliff:document:2.0\" srcLang=\"en\" trgLang=\"{}\">\n", target_lang));
+            output.push_str(&format!("  <file id=\"f1\" original=\"{}\">\n", original));
+        }
+    }
+
+    for (i, Translation { key, source, target, origin, .. }) in translations.iter().enumerate() {
+        let (key, source, target) = (escape(key), escape(source), escape(target));
+        let translate = if origin.is_generated() { " translate=\"no\"" } else { "" };
+        let note = origin_name(*origin);
+        match version {
+            XliffVersion::V1_2 => {
+                let state = match origin {
+                    Origin::Conflict | Origin::Replaced => "final",
+                    Origin::Fallback => "translated",
+                    Origin::Failed => "needs-translation",
+                };
+                output.push_str(&format!("      <trans-unit id=\"{}\" resname=\"{}\"{}>\n", i + 1, key, translate));
+                output.push_str(&format!("        <source>{}</source>\n", source));
+                if origin.is_translated() {
+                    output.push_str(&format!("        <target state=\"{}\">{}</target>\n", state, target));
+                } else {
+                    output.push_str(&format!("        <target state=\"{}\"/>\n", state));
+                }
+                output.push_str(&format!("        <note>{}</note>\n", note));
+                output.push_str("      </trans-unit>\n");
+            }
+            XliffVersion::V2_0 => {
+                let state = match origin {
+                    Origin::Conflict | Origin::Replaced => "final",
+                    Origin::Fallback => "translated",
+                    Origin::Failed => "initial",
+                };
+                output.push_str(&format!("    <unit id=\"u{}\" name=\"{}\"{}>\n", i + 1, key, translate));
+                output.push_str(&format!("      <notes><note category=\"origin\">{}</note></notes>\n", note));
+                output.push_str(&format!("      <segment state=\"{}\">\n", state));
+                output.push_str(&format!("        <source>{}</source>\n", source));
+                if origin.is_translated() {
+                    output.push_str(&format!("        <target>{}</target>\n", target));
+                }
+                output.push_str("      </segment>\n");
+                output.push_str("    </unit>\n");
+            }
+        }
+    }
+
+    match version {
+        XliffVersion::V1_2 => output.push_str("    </body>\n  </file>\n</xliff>\n"),
+        XliffVersion::V2_0 => output.push_str("  </file>\n</xliff>\n"),
+    }
+    output.into_bytes()
+}
+
+/// Exports the outcome of replacing to `path`.
+pub fn export(options: &RuntimeOptions, version: XliffVersion, translations: &[Translation], path: &Path) -> std::io::Result<()> {
+    let original = Path::new(options.main_source_path.as_ref().unwrap())
+        .file_name().unwrap_or_default().to_string_lossy().into_owned();
+    options.output().write(path, &render(version, &original, &options.lang, translations))
+}
+
+/// A unit being read.
+#[derive(Default)]
+struct Unit {
+    line: usize,
+    name: Option<String>,
+    translate: bool,
+    source: String,
+    target: String,
+    /// Some state says the target is not final
+    fuzzy: bool,
+}
+
+impl Unit {
+    fn push(&mut self, field: Field, text: &str) {
+        match field {
+            Field::Source => self.source.push_str(text),
+            Field::Target => self.target.push_str(text),
+        }
+    }
+}
+
+/// Whether a state of either version means the target still needs work.
+fn is_fuzzy_state(state: &str) -> bool {
+    matches!(state, "new" | "needs-translation" | "needs-adaptation" | "needs-l10n" | "initial") || state.starts_with("needs-review")
+}
+
+fn attribute(e: &BytesStart, name: &[u8]) -> quick_xml::Result<Option<String>> {
+    for attr in e.attributes() {
+        let attr = attr?;
+        if attr.key.local_name().as_ref() == name {
+            return Ok(Some(attr.unescape_value()?.into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Source,
+    Target,
+}
+
+/// Reads the translatable units of a document of either version, as entries located at `file:line`.
+pub fn parse(file: &str, text: &str) -> Result<Vec<ImportEntry>, ParseError> {
+    // line of the event being read, counted from the end of the last one
+    let (mut line, mut counted) = (1, 0);
+    let mut reader = Reader::from_str(text);
+    let mut entries = vec![];
+    let mut unit: Option<Unit> = None;
+    // the element whose text is being read
+    let mut reading: Option<Field> = None;
+    // open elements, only sources and targets directly in a unit or segment are read,
+    // not those of `alt-trans` suggestions
+    let mut parents: Vec<Vec<u8>> = vec![];
+    loop {
+        let position = reader.buffer_position().min(text.len());
+        line += text[counted..position].matches('\n').count();
+        counted = position;
+        let error = |e: quick_xml::Error| ParseError { line, message: e.to_string() };
+        let (e, empty) = match reader.read_event().map_err(error)? {
+            Event::Start(e) => (e, false),
+            Event::Empty(e) => (e, true),
+            Event::End(e) => {
+                parents.pop();
+                match e.local_name().as_ref() {
+                    b"trans-unit" | b"unit" => {
+                        if let Some(unit) = unit.take().filter(|x| x.translate) {
+                            entries.push(ImportEntry {
+                                position: format!("{}:{}", file, unit.line),
+                                key: unit.name,
+                                source: unit.source,
+                                target: unit.target,
+                                fuzzy: unit.fuzzy,
+                                obsolete: false,
+                                generated: false,
+                            });
+                        }
+                    }
+                    b"source" | b"target" => reading = None,
+                    _ => {}
+                }
+                continue;
+            }
+            Event::Text(e) => {
+                if let (Some(field), Some(unit)) = (reading, unit.as_mut()) {
+                    unit.push(field, &e.unescape().map_err(error)?);
+                }
+                continue;
+            }
+            Event::CData(e) => {
+                if let (Some(field), Some(unit)) = (reading, unit.as_mut()) {
+                    unit.push(field, &String::from_utf8_lossy(&e.into_inner()));
+                }
+                continue;
+            }
+            Event::Eof => break,
+            _ => continue,
+        };
+        let parent = parents.last().map(|x| x.as_slice());
+        match e.local_name().as_ref() {
+            b"trans-unit" | b"unit" => {
+                let name = match attribute(&e, b"resname").map_err(error)? {
+                    Some(name) => Some(name),
+                    None => attribute(&e, b"name").map_err(error)?,
+                };
+                unit = Some(Unit {
+                    line,
+                    name,
+                    translate: attribute(&e, b"translate").map_err(error)?.as_deref() != Some("no"),
+                    ..Unit::default()
+                });
+            }
+            // the state is on the target in 1.2 and on the segment in 2.0
+            name @ (b"target" | b"segment") => {
+                let direct = matches!((name, parent), (b"target", Some(b"trans-unit" | b"segment")) | (b"segment", Some(b"unit")));
+                if let Some(unit) = unit.as_mut().filter(|_| direct) {
+                    if let Some(state) = attribute(&e, b"state").map_err(error)? {
+                        unit.fuzzy |= is_fuzzy_state(&state);
+                    }
+                    if name == b"target" && !empty {
+                        reading = Some(Field::Target);
+                    }
+                }
+            }
+            b"source" if unit.is_some() && !empty && matches!(parent, Some(b"trans-unit" | b"segment")) => {
+                reading = Some(Field::Source);
+            }
+            _ => {}
+        }
+        if !empty {
+            parents.push(e.local_name().as_ref().to_vec());
+        }
+    }
+    Ok(entries)
+}
+
+/// Merges the translated units of `files` into the fallback.
+pub fn import<P: AsRef<Path>>(options: &RuntimeOptions, files: &[P]) -> std::io::Result<ImportReport> {
+    let mut imported = vec![];
+    for file in files {
+        let file = file.as_ref();
+        let text = std::fs::read_to_string(file)?;
+        imported.extend(parse(&file.display().to_string(), &text).map_err(|e| invalid_data(file, e))?);
+    }
+    exchange::import(options, imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn units(text: &str) -> Vec<(Option<String>, String, String, bool)> {
+        parse("test.xlf", text).unwrap().into_iter().map(|x| (x.key, x.source, x.target, x.fuzzy)).collect()
+    }
+
+    fn unit(key: &str, source: &str, target: &str, fuzzy: bool) -> (Option<String>, String, String, bool) {
+        (Some(key.to_string()), source.to_string(), target.to_string(), fuzzy)
+    }
+
+    #[test]
+    fn alt_trans_is_ignored() {
+        let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+<xliff version="1.2" xmlns="urn:oasis:names:tc:xliff:document:1.2">
+  <file original="GregTech.lang" source-language="en" target-language="zh" datatype="plaintext">
+    <body>
+      <trans-unit id="1" resname="S:a">
+        <source>Iron Plate</source>
+        <target state="translated">铁板</target>
+        <alt-trans match-quality="80">
+          <source>Iron Plates</source>
+          <target state="needs-review-translation">铁板们</target>
+        </alt-trans>
+      </trans-unit>
+      <trans-unit id="2" resname="S:b">
+        <source>Gold <g id="1">Plate</g></source>
+        <alt-trans>
+          <source>Gold <g id="1">Plate</g></source>
+          <target>金板</target>
+        </alt-trans>
+        <target state="translated">金<g id="1">板</g></target>
+      </trans-unit>
+    </body>
+  </file>
+</xliff>
+"#;
+        assert_eq!(units(text), vec![unit("S:a", "Iron Plate", "铁板", false), unit("S:b", "Gold Plate", "金板", false)]);
+    }
+
+    #[test]
+    fn segments_of_version_2() {
+        let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+<xliff version="2.0" xmlns="urn:oasis:names:tc:xliff:document:2.0" srcLang="en" trgLang="zh">
+  <file id="f1">
+    <unit id="1" name="S:a">
+      <segment state="initial">
+        <source>Iron Plate</source>
+        <target>铁板</target>
+      </segment>
+    </unit>
+    <unit id="2" name="S:b" translate="no">
+      <segment state="final">
+        <source>Gold Plate</source>
+        <target>金板</target>
+      </segment>
+    </unit>
+  </file>
+</xliff>
+"#;
+        assert_eq!(units(text), vec![unit("S:a", "Iron Plate", "铁板", true)]);
+    }
+}